@@ -0,0 +1,207 @@
+//! Failure alerting. After every run the outcome is checked for anomalies and a compact
+//! report is sent to the ops webhook. Each kind of alert is only sent once per dedup window
+//! and the state is cleared again by the next healthy run.
+
+/// Maximum number of per-story errors included in a report.
+const MAX_ERRORS_IN_REPORT: usize = 5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Alert {
+    RunFailed(String),
+    Timeout(String),
+    NoStories,
+    ScrapeFailures { failed: usize, total: usize },
+}
+
+impl Alert {
+    /// Key used to de-duplicate alerts across runs.
+    fn kind(&self) -> &'static str {
+        match self {
+            Alert::RunFailed(_) => "run_failed",
+            Alert::Timeout(_) => "timeout",
+            Alert::NoStories => "no_stories",
+            Alert::ScrapeFailures { .. } => "scrape_failures",
+        }
+    }
+}
+
+impl std::fmt::Display for Alert {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Alert::RunFailed(error) => write!(f, "Run failed: {error}"),
+            Alert::Timeout(error) => write!(f, "Run timed out: {error}"),
+            Alert::NoStories => write!(f, "No stories made it through fetching and scoring"),
+            Alert::ScrapeFailures { failed, total } => {
+                write!(f, "Scraping failed for {failed} of {total} stories")
+            }
+        }
+    }
+}
+
+pub(crate) fn detect(
+    run: &crate::run::Run,
+    outcome: crate::run::Outcome,
+    error: Option<&str>,
+    scrape_failure_ratio: f64,
+) -> Vec<Alert> {
+    let mut alerts = Vec::new();
+    let error = error.unwrap_or_default().to_string();
+
+    match outcome {
+        crate::run::Outcome::Success => (),
+        crate::run::Outcome::Failure => alerts.push(Alert::RunFailed(error)),
+        crate::run::Outcome::Timeout => alerts.push(Alert::Timeout(error)),
+    }
+
+    // Triage keeping nothing is a quiet day, not an anomaly. Only stories that were meant to be
+    // scraped and scored count.
+    if outcome == crate::run::Outcome::Success
+        && (run.stories_fetched == 0 || (run.stories_triaged > 0 && run.stories_scored == 0))
+    {
+        alerts.push(Alert::NoStories);
+    }
+
+    let scrape_failures = run
+        .errors
+        .iter()
+        .filter(|e| e.stage == crate::run::Stage::Scrape)
+        .count();
+    if run.stories_triaged > 0
+        && scrape_failures as f64 / run.stories_triaged as f64 > scrape_failure_ratio
+    {
+        alerts.push(Alert::ScrapeFailures {
+            failed: scrape_failures,
+            total: run.stories_triaged,
+        });
+    }
+
+    alerts
+}
+
+fn report(run: &crate::run::Run, alerts: &[Alert]) -> String {
+    let mut message = format!("*AI Summarizer alert for run {}*\n", run.id);
+
+    for alert in alerts {
+        message.push_str(&format!("• {alert}\n"));
+    }
+
+    if !run.errors.is_empty() {
+        message.push_str(&format!("\n{} story errors:\n", run.errors.len()));
+        for error in run.errors.iter().take(MAX_ERRORS_IN_REPORT) {
+            message.push_str(&format!(
+                "• {} {}: {}\n",
+                error.stage, error.story_id, error.error
+            ));
+        }
+    }
+
+    if !run.skipped.is_empty() {
+        message.push_str(&format!(
+            "\n{} stories were skipped at a stage deadline.\n",
+            run.skipped.len()
+        ));
+    }
+
+    message
+}
+
+/// Sends a report for any new alerts to the ops webhook, if one is configured.
+pub(crate) async fn notify(
+    db: &rusqlite::Connection,
+    run: &crate::run::Run,
+    outcome: crate::run::Outcome,
+    error: Option<&str>,
+) -> anyhow::Result<()> {
+    let config = crate::config::config();
+    let Some(url) = config.ops_webhook_url.as_ref() else {
+        return Ok(());
+    };
+
+    let alerts = detect(run, outcome, error, config.alert_scrape_failure_ratio);
+    if alerts.is_empty() {
+        crate::db::clear_alerts(db)?;
+        return Ok(());
+    }
+
+    let now = crate::run::unix_now();
+    let window = config.alert_dedup_window_hours * 60 * 60;
+    let mut new_alerts = Vec::with_capacity(alerts.len());
+    for alert in alerts {
+        match crate::db::get_alert_sent_at(db, alert.kind())? {
+            Some(sent_at) if now - sent_at < window => {
+                tracing::info!(
+                    kind = alert.kind(),
+                    sent_at = sent_at,
+                    "Suppressed duplicate alert"
+                );
+            }
+            _ => new_alerts.push(alert),
+        }
+    }
+
+    if new_alerts.is_empty() {
+        return Ok(());
+    }
+
+    crate::google_chat::send_message(report(run, &new_alerts), url).await?;
+    for alert in &new_alerts {
+        crate::db::set_alert_sent_at(db, alert.kind(), now)?;
+    }
+    tracing::info!(alerts =? new_alerts, "Sent alert to ops channel");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let mut run = crate::run::Run {
+            stories_fetched: 40,
+            // Triage dropped most of the filtered stories, only the rest were scraped.
+            stories_filtered: 30,
+            stories_triaged: 4,
+            stories_scraped: 1,
+            stories_scored: 1,
+            ..Default::default()
+        };
+        for story_id in 0..3 {
+            run.errors.push(crate::run::StoryError {
+                story_id,
+                stage: crate::run::Stage::Scrape,
+                error: "Timeout".to_string(),
+            });
+        }
+        // Stories skipped at the scrape deadline are not scrape failures.
+        run.skipped.push(crate::run::SkippedStory {
+            story_id: 3,
+            stage: crate::run::Stage::Scrape,
+        });
+
+        let alerts = detect(&run, crate::run::Outcome::Success, None, 0.5);
+        assert_eq!(
+            alerts,
+            vec![Alert::ScrapeFailures {
+                failed: 3,
+                total: 4
+            }]
+        );
+
+        run.stories_scored = 0;
+        let alerts = detect(&run, crate::run::Outcome::Success, None, 0.9);
+        assert_eq!(alerts, vec![Alert::NoStories]);
+
+        let alerts = detect(&run, crate::run::Outcome::Failure, Some("boom"), 0.9);
+        assert_eq!(alerts, vec![Alert::RunFailed("boom".to_string())]);
+
+        // Nothing left after triage is not an anomaly.
+        let run = crate::run::Run {
+            stories_fetched: 40,
+            stories_filtered: 30,
+            ..Default::default()
+        };
+        assert!(detect(&run, crate::run::Outcome::Success, None, 0.5).is_empty());
+    }
+}
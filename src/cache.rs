@@ -0,0 +1,212 @@
+//! Persistent cache of model responses. A response is reused when the backend, model, system
+//! prompt, response schema and input text are all unchanged, so re-running after `--reset` or a
+//! ranking change does not bill the same stories again. Changing the prompt or the model changes
+//! the key, which invalidates the old entries without any bookkeeping.
+
+/// Connection used by the model calls. They run in spawned tasks, so they cannot share the
+/// connection of the pipeline. Unset when the cache is disabled.
+static CACHE: std::sync::OnceLock<std::sync::Mutex<rusqlite::Connection>> =
+    std::sync::OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Key {
+    pub(crate) backend: String,
+    pub(crate) model: String,
+    pub(crate) system_prompt_hash: String,
+    pub(crate) schema_hash: String,
+    pub(crate) input_hash: String,
+}
+
+impl Key {
+    pub(crate) fn new(
+        backend: &str,
+        model: &str,
+        system_prompt: &str,
+        schema: &serde_json::Value,
+        input: &str,
+    ) -> Self {
+        Self {
+            backend: backend.to_string(),
+            model: model.to_string(),
+            system_prompt_hash: hash(system_prompt),
+            schema_hash: hash(&schema.to_string()),
+            input_hash: hash(input),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub(crate) struct Stats {
+    pub(crate) backend: String,
+    pub(crate) model: String,
+    pub(crate) entries: i64,
+    pub(crate) hits: i64,
+    /// Tokens that would have been billed again without the cache.
+    pub(crate) tokens_saved: i64,
+}
+
+fn hash(text: &str) -> String {
+    use sha2::Digest;
+    format!("{:x}", sha2::Sha256::digest(text.as_bytes()))
+}
+
+/// Enables the cache for the rest of the process.
+pub(crate) fn enable() -> anyhow::Result<()> {
+    let db = rusqlite::Connection::open(crate::db::DB_PATH)?;
+    db.busy_timeout(std::time::Duration::from_secs(10))?;
+    CACHE
+        .set(std::sync::Mutex::new(db))
+        .map_err(|_| anyhow::anyhow!("Cache already enabled"))
+}
+
+/// Runs `f` on the cache connection. SQLite blocks, so it runs off the async runtime like the
+/// tokenizer work. None when the cache is disabled.
+async fn with_db<T: Send + 'static>(
+    f: impl FnOnce(&rusqlite::Connection) -> anyhow::Result<T> + Send + 'static,
+) -> anyhow::Result<Option<T>> {
+    let Some(db) = CACHE.get() else {
+        return Ok(None);
+    };
+    tokio::task::spawn_blocking(move || f(&db.lock().expect("Cache lock not poisoned")))
+        .await?
+        .map(Some)
+}
+
+/// Stored response for `key`, with usage counting its original tokens as cached rather than
+/// billed. Cache errors are logged and treated as a miss.
+pub(crate) async fn get(key: &Key) -> Option<(String, crate::openai::Usage)> {
+    let key = key.clone();
+    match with_db(move |db| crate::db::get_cached_response(db, &key, crate::run::unix_now())).await
+    {
+        Ok(hit) => hit
+            .flatten()
+            .map(|(response, usage)| (response, cached(&usage))),
+        Err(e) => {
+            tracing::warn!(error =? e, "Error reading response cache");
+            None
+        }
+    }
+}
+
+/// Usage of a response reused from the cache, which costs nothing again.
+fn cached(usage: &crate::openai::Usage) -> crate::openai::Usage {
+    crate::openai::Usage {
+        cached_tokens: usage.total_tokens,
+        ..Default::default()
+    }
+}
+
+pub(crate) async fn put(key: &Key, response: &str, usage: &crate::openai::Usage) {
+    let (key, response, usage) = (key.clone(), response.to_string(), usage.clone());
+    if let Err(e) = with_db(move |db| {
+        crate::db::insert_cached_response(db, &key, &response, &usage, crate::run::unix_now())
+    })
+    .await
+    {
+        tracing::warn!(error =? e, "Error writing response cache");
+    }
+}
+
+pub(crate) fn print_stats(stats: &[Stats], json: bool) -> anyhow::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(stats)?);
+        return Ok(());
+    }
+
+    println!(
+        "{:<10} {:<30} {:>8} {:>8} {:>14}",
+        "Backend", "Model", "Entries", "Hits", "Tokens saved"
+    );
+    for stats in stats {
+        println!(
+            "{:<10} {:<30} {:>8} {:>8} {:>14}",
+            stats.backend, stats.model, stats.entries, stats.hits, stats.tokens_saved
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key() {
+        let schema = serde_json::json!({"type": "object"});
+        let key = Key::new("openai", "gpt-4o-mini", "Summarize", &schema, "Text");
+
+        assert_eq!(
+            key,
+            Key::new("openai", "gpt-4o-mini", "Summarize", &schema, "Text")
+        );
+        assert_ne!(
+            key,
+            Key::new(
+                "openai",
+                "gpt-4o-mini",
+                "Summarize briefly",
+                &schema,
+                "Text"
+            )
+        );
+        assert_ne!(
+            key,
+            Key::new("openai", "gpt-4o", "Summarize", &schema, "Text")
+        );
+        assert_ne!(
+            key.schema_hash,
+            Key::new(
+                "openai",
+                "gpt-4o-mini",
+                "Summarize",
+                &serde_json::json!({"type": "string"}),
+                "Text"
+            )
+            .schema_hash
+        );
+    }
+
+    #[test]
+    fn test_get_and_put() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        crate::db::create_cache_table(&db).unwrap();
+        let key = Key::new(
+            "openai",
+            "gpt-4o-mini",
+            "Summarize",
+            &serde_json::json!({}),
+            "Text",
+        );
+        let usage = crate::openai::Usage {
+            prompt_tokens: 10,
+            completion_tokens: 5,
+            total_tokens: 15,
+            ..Default::default()
+        };
+
+        assert!(
+            crate::db::get_cached_response(&db, &key, 1)
+                .unwrap()
+                .is_none()
+        );
+        crate::db::insert_cached_response(&db, &key, "{}", &usage, 1).unwrap();
+        let (response, cached_usage) = crate::db::get_cached_response(&db, &key, 2)
+            .unwrap()
+            .unwrap();
+        assert_eq!(response, "{}");
+        assert_eq!(cached_usage.total_tokens, 15);
+        assert_eq!(
+            cached(&cached_usage),
+            crate::openai::Usage {
+                cached_tokens: 15,
+                ..Default::default()
+            }
+        );
+
+        let stats = crate::db::get_cache_stats(&db).unwrap();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].hits, 1);
+        assert_eq!(stats[0].tokens_saved, 15);
+    }
+}
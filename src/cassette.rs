@@ -0,0 +1,137 @@
+//! Record and replay of HTTP traffic. Every request made through the shared `CLIENT` goes through
+//! `send`. In record mode the request and response are written to a cassette directory, in replay
+//! mode the recorded response is returned without touching the network. A recorded run can then
+//! be re-run offline to catch changes in scraping, response parsing and message rendering.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Mode {
+    Off,
+    Record,
+    Replay,
+}
+
+impl std::str::FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "off" => Ok(Mode::Off),
+            "record" => Ok(Mode::Record),
+            "replay" => Ok(Mode::Replay),
+            _ => anyhow::bail!("Unknown cassette mode {s}, expected off, record or replay"),
+        }
+    }
+}
+
+/// One recorded request and its response. Headers are not recorded so credentials never end up
+/// in a cassette.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Interaction {
+    method: String,
+    url: String,
+    request_body: Option<String>,
+    status: u16,
+    response_body: String,
+}
+
+#[derive(Debug)]
+pub(crate) struct Response {
+    pub(crate) status: reqwest::StatusCode,
+    pub(crate) body: String,
+}
+
+impl Response {
+    pub(crate) fn error_for_status_ref(&self) -> anyhow::Result<()> {
+        if self.status.is_client_error() || self.status.is_server_error() {
+            anyhow::bail!("HTTP status {}", self.status);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn error_for_status(self) -> anyhow::Result<Self> {
+        self.error_for_status_ref()?;
+        Ok(self)
+    }
+}
+
+/// Requests match on method, url and body, so any change in what is sent misses the cassette.
+fn file_name(method: &str, url: &str, body: Option<&str>) -> String {
+    use sha2::Digest;
+    let key = format!("{method}\n{url}\n{}", body.unwrap_or_default());
+    let hash = format!("{:x}", sha2::Sha256::digest(key.as_bytes()));
+    format!("{}.json", &hash[..16])
+}
+
+/// Sends the request according to the configured cassette mode.
+pub(crate) async fn send(request: reqwest::RequestBuilder) -> anyhow::Result<Response> {
+    let config = crate::config::config();
+    let (client, request) = request.build_split();
+    let request = request?;
+
+    let method = request.method().to_string();
+    let url = request.url().to_string();
+    let body = request
+        .body()
+        .and_then(|b| b.as_bytes())
+        .map(|b| String::from_utf8_lossy(b).into_owned());
+    let path =
+        std::path::Path::new(&config.cassette_dir).join(file_name(&method, &url, body.as_deref()));
+
+    if config.cassette_mode == Mode::Replay {
+        let json = std::fs::read_to_string(&path).map_err(|e| {
+            anyhow::anyhow!("No recorded response for {method} {url} at {path:?}: {e}")
+        })?;
+        let interaction: Interaction = serde_json::from_str(&json)?;
+        return Ok(Response {
+            status: reqwest::StatusCode::from_u16(interaction.status)?,
+            body: interaction.response_body,
+        });
+    }
+
+    let response = client.execute(request).await?;
+    let response = Response {
+        status: response.status(),
+        body: response.text().await?,
+    };
+
+    if config.cassette_mode == Mode::Record {
+        let interaction = Interaction {
+            method,
+            url,
+            request_body: body,
+            status: response.status.as_u16(),
+            response_body: response.body.clone(),
+        };
+        std::fs::create_dir_all(&config.cassette_dir)?;
+        std::fs::write(&path, serde_json::to_string_pretty(&interaction)?)?;
+    }
+
+    Ok(response)
+}
+
+/// Errors are retried unless replaying, where a missing recording will not appear on retry.
+pub(crate) fn retryable(error: anyhow::Error) -> backoff::Error<anyhow::Error> {
+    if crate::config::config().cassette_mode == Mode::Replay {
+        backoff::Error::permanent(error)
+    } else {
+        backoff::Error::transient(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_name() {
+        let name = file_name("GET", "https://example.com/a", None);
+        assert_eq!(name, file_name("GET", "https://example.com/a", Some("")));
+        assert_ne!(name, file_name("POST", "https://example.com/a", None));
+        assert_ne!(
+            file_name("POST", "https://example.com/a", Some("{\"a\":1}")),
+            file_name("POST", "https://example.com/a", Some("{\"a\":2}"))
+        );
+        assert!(name.ends_with(".json"));
+    }
+}
@@ -1,44 +1,445 @@
-#[derive(Debug)]
+#[derive(Debug, serde::Serialize)]
 pub(crate) struct Config {
+    #[serde(skip)]
     pub(crate) api_key: String,
     pub(crate) model: String,
-    pub(crate) system_prompt: String,
+    pub(crate) openai_base_url: String,
+    /// Texts longer than this many tokens are summarized in chunks and then combined.
+    pub(crate) chunk_tokens: usize,
+    /// Story texts are cut to this many tokens at a sentence boundary before summarizing.
+    pub(crate) max_input_tokens: usize,
+    /// Dollars per million tokens of `model`, and of models without `model_prices`.
+    pub(crate) input_price_per_million_tokens: f64,
+    pub(crate) output_price_per_million_tokens: f64,
+    /// Prices of other models, for dry runs and evaluations of them.
+    pub(crate) model_prices: std::collections::BTreeMap<String, crate::tokens::Prices>,
+    pub(crate) profiles: Vec<crate::profile::Profile>,
 
+    pub(crate) hn_api_url: String,
+    /// Number of stories requested from each Hacker News list.
     pub(crate) num_titles_to_request: usize,
-    pub(crate) google_chat_webhook_url: String,
-    pub(crate) max_number_of_stories_to_present: usize,
+    #[serde(skip)]
     pub(crate) log_to_console: bool,
+
+    #[serde(skip)]
+    pub(crate) ops_webhook_url: Option<String>,
+    pub(crate) alert_scrape_failure_ratio: f64,
+    pub(crate) alert_dedup_window_hours: i64,
+
+    /// Rules deciding which fetched stories are dropped or always kept, before triage.
+    pub(crate) filter_rules: crate::filters::Rules,
+
+    /// First pass over titles and domains before scraping.
+    pub(crate) triage_mode: crate::triage::Mode,
+    pub(crate) triage_model: String,
+    /// Stories scoring below this (0-100) are dropped before scraping.
+    pub(crate) triage_threshold: i64,
+    pub(crate) triage_batch_size: usize,
+    pub(crate) triage_keywords: Vec<String>,
+
+    /// Merging of near-duplicate stories after scraping.
+    pub(crate) dedup_mode: crate::dedup::Mode,
+    /// Texts whose SimHashes differ in at most this many of 64 bits are near-duplicates.
+    pub(crate) dedup_max_distance: u32,
+
+    /// Number of times each model scores every story.
+    pub(crate) consensus_samples: usize,
+    pub(crate) consensus_method: crate::consensus::Method,
+    /// Stories where a smaller share of the votes agree (0.0-1.0) are handled by
+    /// `consensus_action`.
+    pub(crate) min_consensus: f64,
+    pub(crate) consensus_action: crate::consensus::Action,
+
+    /// Checks every summary has to pass. Failing summaries are sent back to the model with the
+    /// problems up to `summary_repair_attempts` times.
+    pub(crate) summary_rules: crate::validation::Rules,
+    pub(crate) summary_repair_attempts: usize,
+
+    /// Verification of summaries against the scraped text.
+    pub(crate) grounding_mode: crate::grounding::Mode,
+    pub(crate) grounding_model: String,
+    /// Share of a sentence's words (0.0-1.0) that have to appear in the text.
+    pub(crate) grounding_min_overlap: f64,
+    pub(crate) grounding_action: crate::grounding::Action,
+    /// Summaries with a smaller share of supported sentences are retried and then held back.
+    pub(crate) min_groundedness: f64,
+    pub(crate) grounding_retries: usize,
+
+    /// What a digest does when no story meets the threshold.
+    pub(crate) fallback_policy: crate::fallback::Policy,
+    /// Stories promoted or listed in the quiet day note.
+    pub(crate) fallback_max_stories: usize,
+
+    /// Per-stage deadlines. Stories not finished when a deadline passes are skipped.
+    pub(crate) fetch_deadline_secs: u64,
+    pub(crate) triage_deadline_secs: u64,
+    pub(crate) scrape_deadline_secs: u64,
+    pub(crate) llm_deadline_secs: u64,
+    pub(crate) delivery_deadline_secs: u64,
+
+    /// Record or replay all HTTP traffic to or from `cassette_dir`.
+    #[serde(skip)]
+    pub(crate) cassette_mode: crate::cassette::Mode,
+    #[serde(skip)]
+    pub(crate) cassette_dir: String,
+
+    /// Daemon mode only.
+    pub(crate) poll_interval_secs: u64,
 }
 
+impl Config {
+    /// Hash of the settings that influence the output of a run. Secrets are excluded.
+    pub(crate) fn hash(&self) -> String {
+        use sha2::Digest;
+        let serialized = serde_json::to_string(self).expect("Config to serialize");
+        format!("{:x}", sha2::Sha256::digest(serialized.as_bytes()))
+    }
+}
+
+#[cfg(not(test))]
 static CONFIG: std::sync::LazyLock<Config> = std::sync::LazyLock::new(|| {
     match dotenvy::dotenv() {
         Ok(_) => (),
         Err(e) => tracing::error!(err =? e,
             "Failed to load .env file. Continuing with default values."),
     }
+    load(|name| std::env::var(name))
+});
+
+/// Tests are configured by the settings the cassettes were recorded with, and only by them, so
+/// neither the environment, a local `.env` nor the order the tests run in changes the result.
+#[cfg(test)]
+static CONFIG: std::sync::LazyLock<Config> = std::sync::LazyLock::new(|| {
+    let vars: std::collections::HashMap<String, String> =
+        dotenvy::from_path_iter("tests/fixtures/cassettes/daily.env")
+            .expect("Test settings not found")
+            .collect::<Result<_, _>>()
+            .expect("Invalid test settings");
+    load(|name| {
+        vars.get(name)
+            .cloned()
+            .ok_or(std::env::VarError::NotPresent)
+    })
+});
+
+/// Builds the config from the settings `var` looks up.
+fn load(var: impl Fn(&str) -> Result<String, std::env::VarError>) -> Config {
+    // The top level settings make up the default profile. With `PROFILES` set they are the
+    // fallback for any setting a profile leaves out.
+    let default_profile = crate::profile::Profile {
+        name: "default".to_string(),
+        sources: vec![crate::profile::Source::Top],
+        model: var("OPENAI_MODEL").expect("OPENAI_MODEL not set"),
+        consensus_models: var("CONSENSUS_MODELS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|m| m.trim().to_string())
+            .filter(|m| !m.is_empty())
+            .collect(),
+        system_prompt: match var("SYSTEM_PROMPT_FILE") {
+            Ok(path) => crate::prompt::load(&path).expect("Invalid SYSTEM_PROMPT_FILE"),
+            Err(_) => var("SYSTEM_PROMPT").expect("SYSTEM_PROMPT not set"),
+        },
+        user_prompt: var("USER_PROMPT_FILE")
+            .map(|path| crate::prompt::load(&path).expect("Invalid USER_PROMPT_FILE"))
+            .unwrap_or_else(|_| crate::prompt::DEFAULT_USER_PROMPT.to_string()),
+        audience: var("AUDIENCE").unwrap_or("a technical audience".to_string()),
+        language: var("SUMMARY_LANGUAGE").unwrap_or("eng".to_string()),
+        reading_level: var("READING_LEVEL")
+            .unwrap_or("technical".to_string())
+            .parse()
+            .expect("Invalid READING_LEVEL"),
+        length: var("SUMMARY_LENGTH")
+            .unwrap_or("standard".to_string())
+            .parse()
+            .expect("Invalid SUMMARY_LENGTH"),
+        topics: var("TOPICS")
+            .map(|topics| crate::topics::parse_topics(&topics).expect("Invalid TOPICS"))
+            .unwrap_or_else(|_| crate::topics::default_topics()),
+        max_number_of_stories_to_present: var("MAX_NUMBER_OF_STORIES_TO_PRESENT")
+            .unwrap_or("5".to_string())
+            .parse()
+            .unwrap(),
+        show_rationale: var("SHOW_RATIONALE")
+            .unwrap_or("false".to_string())
+            .parse()
+            .unwrap(),
+        sinks: var("GOOGLE_CHAT_WEBHOOK_URL")
+            .map(|webhook_url| vec![crate::profile::Sink::GoogleChat { webhook_url }])
+            .unwrap_or_default(),
+        digest_interval_secs: var("DIGEST_INTERVAL_SECS")
+            .unwrap_or("86400".to_string())
+            .parse()
+            .unwrap(),
+        ranking: crate::ranking::Weights {
+            relevance: var("RANK_RELEVANCE_WEIGHT")
+                .unwrap_or("1.0".to_string())
+                .parse()
+                .unwrap(),
+            points: var("RANK_POINTS_WEIGHT")
+                .unwrap_or("2.0".to_string())
+                .parse()
+                .unwrap(),
+            comments: var("RANK_COMMENTS_WEIGHT")
+                .unwrap_or("1.0".to_string())
+                .parse()
+                .unwrap(),
+            gravity: var("RANK_GRAVITY")
+                .unwrap_or("0.2".to_string())
+                .parse()
+                .unwrap(),
+            domains: crate::ranking::parse_domain_weights(
+                &var("RANK_DOMAIN_WEIGHTS").unwrap_or_default(),
+            )
+            .expect("Invalid RANK_DOMAIN_WEIGHTS"),
+        },
+    };
+
+    crate::prompt::check_templates(&default_profile.system_prompt, &default_profile.user_prompt)
+        .expect("Invalid prompt templates");
+    crate::style::parse_language(&default_profile.language).expect("Invalid SUMMARY_LANGUAGE");
+
+    let profiles = match var("PROFILES") {
+        Ok(profiles) => {
+            crate::profile::parse_profiles(&profiles, &default_profile).expect("Invalid PROFILES")
+        }
+        Err(_) => {
+            assert!(
+                !default_profile.sinks.is_empty(),
+                "GOOGLE_CHAT_WEBHOOK_URL not set"
+            );
+            vec![default_profile]
+        }
+    };
+
+    let triage_mode = var("TRIAGE_MODE")
+        .unwrap_or("off".to_string())
+        .parse()
+        .expect("Invalid TRIAGE_MODE");
+    let triage_keywords: Vec<String> = var("TRIAGE_KEYWORDS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|k| k.trim().to_string())
+        .filter(|k| !k.is_empty())
+        .collect();
+    assert!(
+        triage_mode != crate::triage::Mode::Keywords || !triage_keywords.is_empty(),
+        "TRIAGE_KEYWORDS not set"
+    );
 
     Config {
-        api_key: std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY not set"),
-        model: std::env::var("OPENAI_MODEL").expect("OPENAI_MODEL not set"),
-        system_prompt: std::env::var("SYSTEM_PROMPT").expect("SYSTEM_PROMPT not set"),
+        api_key: var("OPENAI_API_KEY").expect("OPENAI_API_KEY not set"),
+        model: var("OPENAI_MODEL").expect("OPENAI_MODEL not set"),
+        openai_base_url: var("OPENAI_BASE_URL").unwrap_or("https://api.openai.com/v1".to_string()),
+        chunk_tokens: var("CHUNK_TOKENS")
+            .unwrap_or("8000".to_string())
+            .parse()
+            .unwrap(),
+        max_input_tokens: var("MAX_INPUT_TOKENS")
+            .unwrap_or("50000".to_string())
+            .parse()
+            .unwrap(),
+        input_price_per_million_tokens: var("INPUT_PRICE_PER_MILLION_TOKENS")
+            .unwrap_or("1.1".to_string())
+            .parse()
+            .unwrap(),
+        output_price_per_million_tokens: var("OUTPUT_PRICE_PER_MILLION_TOKENS")
+            .unwrap_or("4.4".to_string())
+            .parse()
+            .unwrap(),
+        model_prices: crate::tokens::parse_prices(&var("MODEL_PRICES").unwrap_or_default())
+            .expect("Invalid MODEL_PRICES"),
+        profiles,
 
-        num_titles_to_request: std::env::var("NUM_TITLES_TO_REQUEST")
+        hn_api_url: var("HN_API_URL")
+            .unwrap_or("https://hacker-news.firebaseio.com/v0".to_string()),
+        num_titles_to_request: var("NUM_TITLES_TO_REQUEST")
             .unwrap_or("60".to_string())
             .parse()
             .unwrap(),
-        google_chat_webhook_url: std::env::var("GOOGLE_CHAT_WEBHOOK_URL")
-            .expect("GOOGLE_CHAT_WEBHOOK_URL not set"),
-        max_number_of_stories_to_present: std::env::var("MAX_NUMBER_OF_STORIES_TO_PRESENT")
-            .unwrap_or("5".to_string())
+        log_to_console: var("LOG_TO_CONSOLE")
+            .expect("LOG_TO_CONSOLE not set")
             .parse()
             .unwrap(),
-        log_to_console: std::env::var("LOG_TO_CONSOLE")
-            .expect("LOG_TO_CONSOLE not set")
+
+        filter_rules: crate::filters::Rules {
+            always_include_domains: crate::filters::parse_domains(
+                &var("FILTER_ALWAYS_INCLUDE_DOMAINS").unwrap_or_default(),
+            ),
+            allow_domains: crate::filters::parse_domains(
+                &var("FILTER_ALLOW_DOMAINS").unwrap_or_default(),
+            ),
+            deny_domains: crate::filters::parse_domains(
+                &var("FILTER_DENY_DOMAINS").unwrap_or_default(),
+            ),
+            title_include: var("FILTER_TITLE_INCLUDE")
+                .ok()
+                .map(|r| regex::Regex::new(&r).expect("Invalid FILTER_TITLE_INCLUDE")),
+            title_exclude: var("FILTER_TITLE_EXCLUDE")
+                .ok()
+                .map(|r| regex::Regex::new(&r).expect("Invalid FILTER_TITLE_EXCLUDE")),
+            min_points: var("FILTER_MIN_POINTS")
+                .unwrap_or("0".to_string())
+                .parse()
+                .unwrap(),
+            min_comments: var("FILTER_MIN_COMMENTS")
+                .unwrap_or("0".to_string())
+                .parse()
+                .unwrap(),
+            max_age_hours: var("FILTER_MAX_AGE_HOURS")
+                .ok()
+                .map(|h| h.parse().expect("Invalid FILTER_MAX_AGE_HOURS")),
+        },
+
+        triage_mode,
+        triage_model: var("TRIAGE_MODEL").unwrap_or("gpt-4o-mini".to_string()),
+        triage_threshold: var("TRIAGE_THRESHOLD")
+            .unwrap_or("50".to_string())
+            .parse()
+            .unwrap(),
+        triage_batch_size: var("TRIAGE_BATCH_SIZE")
+            .unwrap_or("30".to_string())
+            .parse()
+            .unwrap(),
+        triage_keywords,
+
+        dedup_mode: var("DEDUP_MODE")
+            .unwrap_or("text".to_string())
+            .parse()
+            .expect("Invalid DEDUP_MODE"),
+        dedup_max_distance: var("DEDUP_MAX_DISTANCE")
+            .unwrap_or("3".to_string())
+            .parse()
+            .unwrap(),
+
+        consensus_samples: var("CONSENSUS_SAMPLES")
+            .unwrap_or("1".to_string())
+            .parse()
+            .unwrap(),
+        consensus_method: var("CONSENSUS_METHOD")
+            .unwrap_or("majority".to_string())
+            .parse()
+            .expect("Invalid CONSENSUS_METHOD"),
+        min_consensus: var("MIN_CONSENSUS")
+            .unwrap_or("0.0".to_string())
+            .parse()
+            .unwrap(),
+        consensus_action: var("CONSENSUS_ACTION")
+            .unwrap_or("flag".to_string())
+            .parse()
+            .expect("Invalid CONSENSUS_ACTION"),
+
+        summary_rules: crate::validation::Rules {
+            min_key_points: var("SUMMARY_MIN_KEY_POINTS")
+                .unwrap_or("1".to_string())
+                .parse()
+                .unwrap(),
+            max_key_points: var("SUMMARY_MAX_KEY_POINTS")
+                .unwrap_or("7".to_string())
+                .parse()
+                .unwrap(),
+            min_words: var("SUMMARY_MIN_WORDS")
+                .unwrap_or("3".to_string())
+                .parse()
+                .unwrap(),
+            max_words: var("SUMMARY_MAX_WORDS")
+                .unwrap_or("60".to_string())
+                .parse()
+                .unwrap(),
+            forbidden_phrases: var("FORBIDDEN_PHRASES")
+                .unwrap_or(
+                    "cannot access,can't access,unable to access,as an ai,i'm sorry".to_string(),
+                )
+                .split(',')
+                .map(|p| p.trim().to_string())
+                .filter(|p| !p.is_empty())
+                .collect(),
+        },
+        summary_repair_attempts: var("SUMMARY_REPAIR_ATTEMPTS")
+            .unwrap_or("2".to_string())
+            .parse()
+            .unwrap(),
+
+        grounding_mode: var("GROUNDING_MODE")
+            .unwrap_or("off".to_string())
+            .parse()
+            .expect("Invalid GROUNDING_MODE"),
+        grounding_model: var("GROUNDING_MODEL").unwrap_or("gpt-4o-mini".to_string()),
+        grounding_min_overlap: var("GROUNDING_MIN_OVERLAP")
+            .unwrap_or("0.5".to_string())
+            .parse()
+            .unwrap(),
+        grounding_action: var("GROUNDING_ACTION")
+            .unwrap_or("drop".to_string())
+            .parse()
+            .expect("Invalid GROUNDING_ACTION"),
+        min_groundedness: var("MIN_GROUNDEDNESS")
+            .unwrap_or("0.5".to_string())
+            .parse()
+            .unwrap(),
+        grounding_retries: var("GROUNDING_RETRIES")
+            .unwrap_or("1".to_string())
+            .parse()
+            .unwrap(),
+
+        fallback_policy: var("FALLBACK_POLICY")
+            .unwrap_or("quiet".to_string())
+            .parse()
+            .expect("Invalid FALLBACK_POLICY"),
+        fallback_max_stories: var("FALLBACK_MAX_STORIES")
+            .unwrap_or("3".to_string())
+            .parse()
+            .unwrap(),
+
+        ops_webhook_url: var("OPS_WEBHOOK_URL").ok().filter(|url| !url.is_empty()),
+        alert_scrape_failure_ratio: var("ALERT_SCRAPE_FAILURE_RATIO")
+            .unwrap_or("0.5".to_string())
+            .parse()
+            .unwrap(),
+        alert_dedup_window_hours: var("ALERT_DEDUP_WINDOW_HOURS")
+            .unwrap_or("24".to_string())
+            .parse()
+            .unwrap(),
+
+        fetch_deadline_secs: var("FETCH_DEADLINE_SECS")
+            .unwrap_or("120".to_string())
+            .parse()
+            .unwrap(),
+        triage_deadline_secs: var("TRIAGE_DEADLINE_SECS")
+            .unwrap_or("120".to_string())
+            .parse()
+            .unwrap(),
+        scrape_deadline_secs: var("SCRAPE_DEADLINE_SECS")
+            .unwrap_or("600".to_string())
+            .parse()
+            .unwrap(),
+        llm_deadline_secs: var("LLM_DEADLINE_SECS")
+            .unwrap_or("1200".to_string())
+            .parse()
+            .unwrap(),
+        delivery_deadline_secs: var("DELIVERY_DEADLINE_SECS")
+            .unwrap_or("60".to_string())
+            .parse()
+            .unwrap(),
+
+        cassette_mode: var("CASSETTE_MODE")
+            .unwrap_or("off".to_string())
+            .parse()
+            .expect("Invalid CASSETTE_MODE"),
+        cassette_dir: var("CASSETTE_DIR").unwrap_or("cassettes".to_string()),
+
+        poll_interval_secs: var("POLL_INTERVAL_SECS")
+            .unwrap_or("300".to_string())
             .parse()
             .unwrap(),
     }
-});
+}
 
 pub(crate) fn config() -> &'static Config {
     &CONFIG
 }
+
+/// Instant at which a stage that starts now has to be finished.
+pub(crate) fn deadline(secs: u64) -> tokio::time::Instant {
+    tokio::time::Instant::now() + std::time::Duration::from_secs(secs)
+}
@@ -0,0 +1,191 @@
+//! Consensus scoring. A single model's labels are noisy, so a story can be scored by several
+//! models, several samples of each, or both. The votes are combined per topic and the share of
+//! votes that agree with the combined label is kept as a confidence signal. Stories the votes
+//! disagree on can be flagged in the digest or left out of it.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Method {
+    /// The label most votes agree on. Ties go to the less relevant label.
+    Majority,
+    /// The label whose rank is closest to the mean rank of the voted labels. Relevance scores
+    /// play no part in it.
+    MeanRank,
+    /// The label the mean relevance score of the votes maps to, see [`score_rank`]. Every topic
+    /// gets the label of the one score, so it suits profiles with a single topic.
+    MeanScore,
+}
+
+impl std::str::FromStr for Method {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "majority" => Ok(Method::Majority),
+            "mean_rank" => Ok(Method::MeanRank),
+            "mean_score" => Ok(Method::MeanScore),
+            _ => anyhow::bail!(
+                "Unknown consensus method {s}, expected majority, mean_rank or mean_score"
+            ),
+        }
+    }
+}
+
+/// What happens to stories with a consensus below `MIN_CONSENSUS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Action {
+    /// Shown in the digest with a note.
+    Flag,
+    /// Left out of the digest.
+    Exclude,
+}
+
+impl std::str::FromStr for Action {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "flag" => Ok(Action::Flag),
+            "exclude" => Ok(Action::Exclude),
+            _ => anyhow::bail!("Unknown consensus action {s}, expected flag or exclude"),
+        }
+    }
+}
+
+/// Model and sample number of every vote cast on a story of the profile. Without consensus
+/// models only the profile's own model votes.
+pub(crate) fn voters(profile: &crate::profile::Profile) -> Vec<(&str, usize)> {
+    let config = crate::config::config();
+    let models = if profile.consensus_models.is_empty() {
+        std::slice::from_ref(&profile.model)
+    } else {
+        &profile.consensus_models[..]
+    };
+    models
+        .iter()
+        .flat_map(|model| (0..config.consensus_samples.max(1)).map(move |s| (model.as_str(), s)))
+        .collect()
+}
+
+/// Rank of the label a relevance score of 0-100 maps to. The range is split evenly across the
+/// labels with the most relevant at the top, so with four labels 75 and above is the first.
+pub(crate) fn score_rank(score: f64, num_labels: usize) -> usize {
+    let rank = ((100.0 - score.clamp(0.0, 100.0)) * num_labels as f64 / 100.0).ceil() as usize;
+    rank.saturating_sub(1).min(num_labels - 1)
+}
+
+/// Combines the classifications of every vote into one per topic. `scores` are the relevance
+/// scores of the votes, used by [`Method::MeanScore`]. Also returns the consensus, the share of
+/// votes agreeing with the combined label of the topic they agree on least.
+pub(crate) fn combine(
+    topics: &[crate::topics::Topic],
+    votes: &[Vec<crate::topics::Classification>],
+    scores: &[i64],
+    method: Method,
+) -> anyhow::Result<(Vec<crate::topics::Classification>, f64)> {
+    if votes.is_empty() {
+        anyhow::bail!("No votes to combine");
+    }
+    let mean_score = scores.iter().sum::<i64>() as f64 / scores.len().max(1) as f64;
+
+    let mut labels = std::collections::BTreeMap::new();
+    let mut consensus: f64 = 1.0;
+    for (i, topic) in topics.iter().enumerate() {
+        let ranks: Vec<usize> = votes.iter().map(|vote| vote[i].rank).collect();
+        let rank = match method {
+            Method::Majority => (0..topic.labels.len())
+                .max_by_key(|rank| (ranks.iter().filter(|r| *r == rank).count(), *rank))
+                .expect("Topic has labels"),
+            Method::MeanRank => {
+                let mean = ranks.iter().sum::<usize>() as f64 / ranks.len() as f64;
+                mean.round() as usize
+            }
+            Method::MeanScore => score_rank(mean_score, topic.labels.len()),
+        };
+        let agreeing = ranks.iter().filter(|r| **r == rank).count();
+        consensus = consensus.min(agreeing as f64 / ranks.len() as f64);
+        labels.insert(topic.name.clone(), topic.labels[rank].clone());
+    }
+
+    Ok((crate::topics::classify(topics, &labels)?, consensus))
+}
+
+/// Whether the votes on the story disagree too much. Stories scored by a single vote have no
+/// consensus and are never low.
+pub(crate) fn is_low(consensus: Option<f64>) -> bool {
+    consensus.is_some_and(|c| c < crate::config::config().min_consensus)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vote(topics: &[crate::topics::Topic], label: &str) -> Vec<crate::topics::Classification> {
+        let labels = [(topics[0].name.clone(), label.to_string())].into();
+        crate::topics::classify(topics, &labels).unwrap()
+    }
+
+    #[test]
+    fn test_voters() {
+        let profile = crate::profile::tests::profile();
+        assert_eq!(voters(&profile), [("gpt-4o-mini", 0)]);
+
+        let profile = crate::profile::Profile {
+            consensus_models: vec!["gpt-4o".to_string(), "o3-mini".to_string()],
+            ..profile
+        };
+        assert_eq!(voters(&profile), [("gpt-4o", 0), ("o3-mini", 0)]);
+    }
+
+    #[test]
+    fn test_combine() {
+        let topics = crate::topics::default_topics();
+        let votes: Vec<_> = ["High", "Medium", "High", "Zero"]
+            .into_iter()
+            .map(|label| vote(&topics, label))
+            .collect();
+
+        let scores = [90, 60, 80, 0];
+
+        let (classifications, consensus) =
+            combine(&topics, &votes, &scores, Method::Majority).unwrap();
+        assert_eq!(classifications[0].label, "High");
+        assert!(classifications[0].included);
+        assert_eq!(consensus, 0.5);
+
+        // Ranks 0, 1, 0 and 3 have a mean of 1.
+        let (classifications, consensus) =
+            combine(&topics, &votes, &scores, Method::MeanRank).unwrap();
+        assert_eq!(classifications[0].label, "Medium");
+        assert_eq!(consensus, 0.25);
+
+        // Scores 90, 60, 80 and 0 have a mean of 57.5.
+        let (classifications, consensus) =
+            combine(&topics, &votes, &scores, Method::MeanScore).unwrap();
+        assert_eq!(classifications[0].label, "Medium");
+        assert_eq!(consensus, 0.25);
+
+        let tie = [vote(&topics, "High"), vote(&topics, "Medium")];
+        let (classifications, _) = combine(&topics, &tie, &[90, 60], Method::Majority).unwrap();
+        assert_eq!(classifications[0].label, "Medium");
+
+        assert!(combine(&topics, &[], &[], Method::Majority).is_err());
+    }
+
+    #[test]
+    fn test_score_rank() {
+        // Four labels split 0-100 into quarters, most relevant first.
+        let ranks: Vec<_> = [100.0, 75.0, 74.5, 50.0, 49.9, 25.0, 24.0, 0.0]
+            .into_iter()
+            .map(|score| score_rank(score, 4))
+            .collect();
+        assert_eq!(ranks, [0, 0, 1, 1, 2, 2, 3, 3]);
+
+        assert_eq!(score_rank(50.0, 2), 0);
+        assert_eq!(score_rank(49.0, 2), 1);
+        assert_eq!(score_rank(120.0, 3), 0);
+        assert_eq!(score_rank(-5.0, 3), 2);
+        assert_eq!(score_rank(42.0, 1), 0);
+    }
+}
@@ -1,39 +1,732 @@
+pub(crate) const DB_PATH: &str = "./db.sqlite";
+
 pub(crate) fn open_db(reset: bool) -> anyhow::Result<rusqlite::Connection> {
-    let db = rusqlite::Connection::open("./db.sqlite")?;
+    let db = rusqlite::Connection::open(DB_PATH)?;
+    init_db(&db, reset)?;
+    Ok(db)
+}
+
+/// Creates and migrates the tables.
+pub(crate) fn init_db(db: &rusqlite::Connection, reset: bool) -> anyhow::Result<()> {
+    migrate_stories_to_profiles(db)?;
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS stories (
+            profile TEXT NOT NULL,
+            id INTEGER NOT NULL,
+            PRIMARY KEY (profile, id)
+        )",
+        (),
+    )?;
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS runs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            started_at INTEGER NOT NULL,
+            finished_at INTEGER,
+            outcome TEXT,
+            error TEXT,
+            config_hash TEXT NOT NULL,
+            model TEXT NOT NULL,
+            stories_fetched INTEGER NOT NULL DEFAULT 0,
+            stories_filtered INTEGER NOT NULL DEFAULT 0,
+            stories_triaged INTEGER NOT NULL DEFAULT 0,
+            stories_scraped INTEGER NOT NULL DEFAULT 0,
+            stories_scored INTEGER NOT NULL DEFAULT 0,
+            stories_sent INTEGER NOT NULL DEFAULT 0,
+            stories_skipped INTEGER NOT NULL DEFAULT 0,
+            prompt_tokens INTEGER NOT NULL DEFAULT 0,
+            completion_tokens INTEGER NOT NULL DEFAULT 0,
+            total_tokens INTEGER NOT NULL DEFAULT 0,
+            cached_tokens INTEGER NOT NULL DEFAULT 0
+        )",
+        (),
+    )?;
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS run_errors (
+            run_id INTEGER NOT NULL REFERENCES runs(id),
+            story_id INTEGER NOT NULL,
+            stage TEXT NOT NULL,
+            error TEXT NOT NULL
+        )",
+        (),
+    )?;
+
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS run_skips (
+            run_id INTEGER NOT NULL REFERENCES runs(id),
+            story_id INTEGER NOT NULL,
+            stage TEXT NOT NULL
+        )",
+        (),
+    )?;
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS filter_matches (
+            run_id INTEGER NOT NULL REFERENCES runs(id),
+            story_id INTEGER NOT NULL,
+            rule TEXT NOT NULL,
+            included INTEGER NOT NULL
+        )",
+        (),
+    )?;
+
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS run_fallbacks (
+            run_id INTEGER NOT NULL REFERENCES runs(id),
+            profile TEXT NOT NULL,
+            policy TEXT NOT NULL,
+            num_stories INTEGER NOT NULL
+        )",
+        (),
+    )?;
+
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS alerts (kind TEXT PRIMARY KEY, sent_at INTEGER NOT NULL)",
+        (),
+    )?;
+
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS items (
+            id INTEGER PRIMARY KEY,
+            json TEXT NOT NULL,
+            fetched_at INTEGER NOT NULL
+        )",
+        (),
+    )?;
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS item_snapshots (
+            id INTEGER NOT NULL,
+            polled_at INTEGER NOT NULL,
+            score INTEGER NOT NULL,
+            descendants INTEGER
+        )",
+        (),
+    )?;
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS story_lists (
+            source TEXT NOT NULL,
+            rank INTEGER NOT NULL,
+            id INTEGER NOT NULL,
+            PRIMARY KEY (source, rank)
+        )",
+        (),
+    )?;
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS story_scores (
+            run_id INTEGER NOT NULL REFERENCES runs(id),
+            profile TEXT NOT NULL,
+            id INTEGER NOT NULL,
+            labels TEXT NOT NULL,
+            relevance_score INTEGER,
+            rationale TEXT,
+            confidence REAL,
+            summary TEXT,
+            consensus REAL,
+            groundedness REAL,
+            system_prompt_hash TEXT,
+            user_prompt_hash TEXT,
+            source_language TEXT,
+            filter_rule TEXT
+        )",
+        (),
+    )?;
     db.execute(
-        "CREATE TABLE IF NOT EXISTS stories (id INTEGER PRIMARY KEY)",
+        "CREATE TABLE IF NOT EXISTS tags (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            kind TEXT NOT NULL,
+            name TEXT NOT NULL,
+            normalized TEXT NOT NULL,
+            UNIQUE (kind, normalized)
+        )",
         (),
     )?;
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS story_tags (
+            story_id INTEGER NOT NULL,
+            tag_id INTEGER NOT NULL REFERENCES tags(id),
+            tagged_at INTEGER NOT NULL,
+            PRIMARY KEY (story_id, tag_id)
+        )",
+        (),
+    )?;
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS profile_runs (
+            profile TEXT PRIMARY KEY,
+            last_run_at INTEGER NOT NULL
+        )",
+        (),
+    )?;
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS hn_state (key TEXT PRIMARY KEY, value INTEGER NOT NULL)",
+        (),
+    )?;
+    create_cache_table(db)?;
 
     if reset {
         db.execute("DELETE FROM stories", ())?;
         tracing::info!("Reset DB");
     }
 
-    Ok(db)
+    Ok(())
 }
 
-pub(crate) fn get_processed_stories(db: &rusqlite::Connection) -> anyhow::Result<Vec<i64>> {
-    let mut stmt = db.prepare("SELECT id FROM stories")?;
+/// Before profiles existed `stories` only held the ids of the single digest. Those are moved
+/// over to the default profile.
+fn migrate_stories_to_profiles(db: &rusqlite::Connection) -> anyhow::Result<()> {
+    let has_stories: bool = db.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'stories'",
+        (),
+        |row| row.get(0),
+    )?;
+    let has_profile: bool = db.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('stories') WHERE name = 'profile'",
+        (),
+        |row| row.get(0),
+    )?;
+    if !has_stories || has_profile {
+        return Ok(());
+    }
 
-    let mut ids = Vec::new();
-    for row in stmt.query_map([], |row| row.get(0))? {
-        ids.push(row?);
+    db.execute_batch(
+        "BEGIN;
+        ALTER TABLE stories RENAME TO stories_without_profile;
+        CREATE TABLE stories (
+            profile TEXT NOT NULL,
+            id INTEGER NOT NULL,
+            PRIMARY KEY (profile, id)
+        );
+        INSERT INTO stories (profile, id) SELECT 'default', id FROM stories_without_profile;
+        DROP TABLE stories_without_profile;
+        COMMIT;",
+    )?;
+    tracing::info!("Migrated processed stories to the default profile");
+    Ok(())
+}
+
+pub(crate) fn get_processed_stories(
+    db: &rusqlite::Connection,
+    profile: &str,
+) -> anyhow::Result<std::collections::HashSet<i64>> {
+    let mut stmt = db.prepare("SELECT id FROM stories WHERE profile = ?")?;
+
+    let mut ids = std::collections::HashSet::new();
+    for row in stmt.query_map((profile,), |row| row.get(0))? {
+        ids.insert(row?);
     }
 
-    tracing::info!(num = ids.len(), "Got processed stories");
+    tracing::info!(num = ids.len(), profile = profile, "Got processed stories");
     Ok(ids)
 }
 
 pub(crate) fn insert_stories(
     db: &rusqlite::Connection,
+    profile: &str,
     stories: &[crate::Story],
 ) -> anyhow::Result<()> {
-    let mut stmt = db.prepare("INSERT INTO stories (id) VALUES (?) ON CONFLICT(id) DO NOTHING")?;
+    let mut stmt = db.prepare(
+        "INSERT INTO stories (profile, id) VALUES (?, ?) ON CONFLICT(profile, id) DO NOTHING",
+    )?;
 
     for story in stories {
-        stmt.execute((story.id,))?;
+        stmt.execute((profile, story.id))?;
+        // Duplicates were shown with the story, they should not come back on their own.
+        for duplicate in &story.duplicates {
+            stmt.execute((profile, duplicate.id))?;
+        }
     }
 
     Ok(())
 }
+
+/// Stores the model's verdict for every scored story, sent or not.
+pub(crate) fn insert_story_scores(
+    db: &rusqlite::Connection,
+    run_id: i64,
+    profile: &crate::profile::Profile,
+    stories: &[crate::Story],
+) -> anyhow::Result<()> {
+    let mut stmt = db.prepare(
+        "INSERT INTO story_scores
+            (run_id, profile, id, labels, relevance_score, rationale, confidence, summary,
+            consensus, groundedness, system_prompt_hash, user_prompt_hash, source_language,
+            filter_rule)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )?;
+    // Which version of the prompts produced the summaries. The system prompt is hashed with the
+    // style instructions added to it, so a change of language, reading level or length shows.
+    let user_prompt_hash = crate::prompt::hash(&profile.user_prompt);
+
+    for story in stories {
+        let system_prompt_hash =
+            crate::prompt::hash(&crate::openai::system_prompt_template(story, profile));
+        stmt.execute(rusqlite::params![
+            run_id,
+            profile.name,
+            story.id,
+            serde_json::to_string(&story.classifications)?,
+            story.relevance_score,
+            &story.rationale,
+            story.confidence,
+            serde_json::to_string(&story.summary)?,
+            story.consensus,
+            story.groundedness,
+            system_prompt_hash,
+            user_prompt_hash,
+            story.source_language,
+            story.filter_rule,
+        ])?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn insert_run(
+    db: &rusqlite::Connection,
+    started_at: i64,
+    config_hash: &str,
+    model: &str,
+) -> anyhow::Result<i64> {
+    db.execute(
+        "INSERT INTO runs (started_at, config_hash, model) VALUES (?, ?, ?)",
+        (started_at, config_hash, model),
+    )?;
+    Ok(db.last_insert_rowid())
+}
+
+pub(crate) fn finish_run(
+    db: &rusqlite::Connection,
+    run: &crate::run::Run,
+    finished_at: i64,
+    outcome: crate::run::Outcome,
+    error: Option<&str>,
+) -> anyhow::Result<()> {
+    db.execute(
+        "UPDATE runs SET
+            finished_at = ?, outcome = ?, error = ?,
+            stories_fetched = ?, stories_filtered = ?, stories_triaged = ?, stories_scraped = ?,
+            stories_scored = ?, stories_sent = ?, stories_skipped = ?,
+            prompt_tokens = ?, completion_tokens = ?, total_tokens = ?, cached_tokens = ?
+        WHERE id = ?",
+        rusqlite::params![
+            finished_at,
+            outcome.to_string(),
+            error,
+            run.stories_fetched,
+            run.stories_filtered,
+            run.stories_triaged,
+            run.stories_scraped,
+            run.stories_scored,
+            run.stories_sent,
+            run.stories_skipped,
+            run.usage.prompt_tokens,
+            run.usage.completion_tokens,
+            run.usage.total_tokens,
+            run.usage.cached_tokens,
+            run.id,
+        ],
+    )?;
+
+    let mut stmt =
+        db.prepare("INSERT INTO run_errors (run_id, story_id, stage, error) VALUES (?, ?, ?, ?)")?;
+    for error in &run.errors {
+        stmt.execute((
+            run.id,
+            error.story_id,
+            error.stage.to_string(),
+            &error.error,
+        ))?;
+    }
+
+    let mut stmt =
+        db.prepare("INSERT INTO run_skips (run_id, story_id, stage) VALUES (?, ?, ?)")?;
+    for skipped in &run.skipped {
+        stmt.execute((run.id, skipped.story_id, skipped.stage.to_string()))?;
+    }
+
+    let mut stmt = db.prepare(
+        "INSERT INTO filter_matches (run_id, story_id, rule, included) VALUES (?, ?, ?, ?)",
+    )?;
+    for filter_match in &run.filter_matches {
+        stmt.execute((
+            run.id,
+            filter_match.story_id,
+            &filter_match.rule,
+            filter_match.included,
+        ))?;
+    }
+
+    let mut stmt = db.prepare(
+        "INSERT INTO run_fallbacks (run_id, profile, policy, num_stories) VALUES (?, ?, ?, ?)",
+    )?;
+    for fallback in &run.fallbacks {
+        stmt.execute((
+            run.id,
+            &fallback.profile,
+            fallback.policy.to_string(),
+            fallback.num_stories,
+        ))?;
+    }
+
+    Ok(())
+}
+
+/// Returns when an alert of the given kind was last sent, if it has not been resolved since.
+pub(crate) fn get_alert_sent_at(
+    db: &rusqlite::Connection,
+    kind: &str,
+) -> anyhow::Result<Option<i64>> {
+    use rusqlite::OptionalExtension;
+    Ok(db
+        .query_row(
+            "SELECT sent_at FROM alerts WHERE kind = ?",
+            (kind,),
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+pub(crate) fn set_alert_sent_at(
+    db: &rusqlite::Connection,
+    kind: &str,
+    sent_at: i64,
+) -> anyhow::Result<()> {
+    db.execute(
+        "INSERT INTO alerts (kind, sent_at) VALUES (?, ?)
+        ON CONFLICT(kind) DO UPDATE SET sent_at = excluded.sent_at",
+        (kind, sent_at),
+    )?;
+    Ok(())
+}
+
+/// Clears all alert state so the next failure is reported immediately.
+pub(crate) fn clear_alerts(db: &rusqlite::Connection) -> anyhow::Result<()> {
+    db.execute("DELETE FROM alerts", ())?;
+    Ok(())
+}
+
+pub(crate) fn get_profile_last_run_at(
+    db: &rusqlite::Connection,
+    profile: &str,
+) -> anyhow::Result<Option<i64>> {
+    use rusqlite::OptionalExtension;
+    Ok(db
+        .query_row(
+            "SELECT last_run_at FROM profile_runs WHERE profile = ?",
+            (profile,),
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+pub(crate) fn set_profile_last_run_at(
+    db: &rusqlite::Connection,
+    profile: &str,
+    last_run_at: i64,
+) -> anyhow::Result<()> {
+    db.execute(
+        "INSERT INTO profile_runs (profile, last_run_at) VALUES (?, ?)
+        ON CONFLICT(profile) DO UPDATE SET last_run_at = excluded.last_run_at",
+        (profile, last_run_at),
+    )?;
+    Ok(())
+}
+
+pub(crate) fn get_cached_item(
+    db: &rusqlite::Connection,
+    id: i64,
+) -> anyhow::Result<Option<String>> {
+    use rusqlite::OptionalExtension;
+    Ok(db
+        .query_row("SELECT json FROM items WHERE id = ?", (id,), |row| {
+            row.get(0)
+        })
+        .optional()?)
+}
+
+pub(crate) fn upsert_item(
+    db: &rusqlite::Connection,
+    id: i64,
+    json: &str,
+    fetched_at: i64,
+) -> anyhow::Result<()> {
+    db.execute(
+        "INSERT INTO items (id, json, fetched_at) VALUES (?, ?, ?)
+        ON CONFLICT(id) DO UPDATE SET json = excluded.json, fetched_at = excluded.fetched_at",
+        (id, json, fetched_at),
+    )?;
+    Ok(())
+}
+
+pub(crate) fn insert_item_snapshot(
+    db: &rusqlite::Connection,
+    story: &crate::Story,
+    polled_at: i64,
+) -> anyhow::Result<()> {
+    db.execute(
+        "INSERT INTO item_snapshots (id, polled_at, score, descendants) VALUES (?, ?, ?, ?)",
+        (story.id, polled_at, story.score, story.descendants),
+    )?;
+    Ok(())
+}
+
+pub(crate) fn get_story_list(
+    db: &rusqlite::Connection,
+    source: crate::profile::Source,
+) -> anyhow::Result<Vec<i64>> {
+    let mut stmt = db.prepare("SELECT id FROM story_lists WHERE source = ? ORDER BY rank")?;
+
+    let mut ids = Vec::new();
+    for row in stmt.query_map((source.to_string(),), |row| row.get(0))? {
+        ids.push(row?);
+    }
+    Ok(ids)
+}
+
+pub(crate) fn set_story_list(
+    db: &rusqlite::Connection,
+    source: crate::profile::Source,
+    ids: &[i64],
+) -> anyhow::Result<()> {
+    db.execute(
+        "DELETE FROM story_lists WHERE source = ?",
+        (source.to_string(),),
+    )?;
+    let mut stmt = db.prepare("INSERT INTO story_lists (source, rank, id) VALUES (?, ?, ?)")?;
+    for (rank, id) in ids.iter().enumerate() {
+        stmt.execute((source.to_string(), rank, id))?;
+    }
+    Ok(())
+}
+
+pub(crate) fn get_hn_state(db: &rusqlite::Connection, key: &str) -> anyhow::Result<Option<i64>> {
+    use rusqlite::OptionalExtension;
+    Ok(db
+        .query_row("SELECT value FROM hn_state WHERE key = ?", (key,), |row| {
+            row.get(0)
+        })
+        .optional()?)
+}
+
+pub(crate) fn set_hn_state(db: &rusqlite::Connection, key: &str, value: i64) -> anyhow::Result<()> {
+    db.execute(
+        "INSERT INTO hn_state (key, value) VALUES (?, ?)
+        ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        (key, value),
+    )?;
+    Ok(())
+}
+
+/// Returns the id of the tag, creating it with `name` as display name if it is new.
+pub(crate) fn upsert_tag(
+    db: &rusqlite::Connection,
+    kind: &str,
+    name: &str,
+    normalized: &str,
+) -> anyhow::Result<i64> {
+    db.execute(
+        "INSERT INTO tags (kind, name, normalized) VALUES (?, ?, ?)
+        ON CONFLICT(kind, normalized) DO NOTHING",
+        (kind, name, normalized),
+    )?;
+    Ok(db.query_row(
+        "SELECT id FROM tags WHERE kind = ? AND normalized = ?",
+        (kind, normalized),
+        |row| row.get(0),
+    )?)
+}
+
+pub(crate) fn insert_story_tag(
+    db: &rusqlite::Connection,
+    story_id: i64,
+    tag_id: i64,
+    tagged_at: i64,
+) -> anyhow::Result<()> {
+    db.execute(
+        "INSERT INTO story_tags (story_id, tag_id, tagged_at) VALUES (?, ?, ?)
+        ON CONFLICT(story_id, tag_id) DO NOTHING",
+        (story_id, tag_id, tagged_at),
+    )?;
+    Ok(())
+}
+
+/// Number of stories mentioning each entity per week, most recent week first.
+pub(crate) fn get_weekly_mentions(
+    db: &rusqlite::Connection,
+    since: i64,
+) -> anyhow::Result<Vec<crate::trends::WeeklyMentions>> {
+    let mut stmt = db.prepare(
+        "SELECT strftime('%G-W%V', story_tags.tagged_at, 'unixepoch') AS week,
+            tags.kind, tags.name, COUNT(*) AS mentions
+        FROM story_tags JOIN tags ON tags.id = story_tags.tag_id
+        WHERE story_tags.tagged_at >= ?
+        GROUP BY week, tags.id
+        ORDER BY week DESC, mentions DESC, tags.name",
+    )?;
+
+    let mut mentions = Vec::new();
+    for row in stmt.query_map((since,), |row| {
+        Ok(crate::trends::WeeklyMentions {
+            week: row.get(0)?,
+            kind: row.get(1)?,
+            name: row.get(2)?,
+            mentions: row.get(3)?,
+        })
+    })? {
+        mentions.push(row?);
+    }
+    Ok(mentions)
+}
+
+/// Entities whose first mention is after `since`, with at least `min_mentions` stories.
+pub(crate) fn get_emerging_entities(
+    db: &rusqlite::Connection,
+    since: i64,
+    min_mentions: i64,
+) -> anyhow::Result<Vec<crate::trends::EmergingEntity>> {
+    let mut stmt = db.prepare(
+        "SELECT tags.kind, tags.name, MIN(story_tags.tagged_at) AS first_seen,
+            COUNT(*) AS mentions
+        FROM story_tags JOIN tags ON tags.id = story_tags.tag_id
+        GROUP BY tags.id
+        HAVING first_seen >= ? AND mentions >= ?
+        ORDER BY mentions DESC, first_seen DESC",
+    )?;
+
+    let mut entities = Vec::new();
+    for row in stmt.query_map((since, min_mentions), |row| {
+        Ok(crate::trends::EmergingEntity {
+            kind: row.get(0)?,
+            name: row.get(1)?,
+            first_seen: row.get(2)?,
+            mentions: row.get(3)?,
+        })
+    })? {
+        entities.push(row?);
+    }
+    Ok(entities)
+}
+
+/// Not cleared by `--reset`, so re-running stories uses the cached responses.
+pub(crate) fn create_cache_table(db: &rusqlite::Connection) -> anyhow::Result<()> {
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS llm_cache (
+            backend TEXT NOT NULL,
+            model TEXT NOT NULL,
+            system_prompt_hash TEXT NOT NULL,
+            schema_hash TEXT NOT NULL,
+            input_hash TEXT NOT NULL,
+            response TEXT NOT NULL,
+            prompt_tokens INTEGER NOT NULL,
+            completion_tokens INTEGER NOT NULL,
+            total_tokens INTEGER NOT NULL,
+            created_at INTEGER NOT NULL,
+            hits INTEGER NOT NULL DEFAULT 0,
+            last_hit_at INTEGER,
+            PRIMARY KEY (backend, model, system_prompt_hash, schema_hash, input_hash)
+        )",
+        (),
+    )?;
+    Ok(())
+}
+
+/// Returns the cached response and its original usage, counting the hit.
+pub(crate) fn get_cached_response(
+    db: &rusqlite::Connection,
+    key: &crate::cache::Key,
+    now: i64,
+) -> anyhow::Result<Option<(String, crate::openai::Usage)>> {
+    use rusqlite::OptionalExtension;
+    let params = (
+        &key.backend,
+        &key.model,
+        &key.system_prompt_hash,
+        &key.schema_hash,
+        &key.input_hash,
+    );
+
+    let hit = db
+        .query_row(
+            "SELECT response, prompt_tokens, completion_tokens, total_tokens FROM llm_cache
+            WHERE backend = ? AND model = ? AND system_prompt_hash = ? AND schema_hash = ?
+                AND input_hash = ?",
+            params,
+            |row| {
+                Ok((
+                    row.get(0)?,
+                    crate::openai::Usage {
+                        prompt_tokens: row.get(1)?,
+                        completion_tokens: row.get(2)?,
+                        total_tokens: row.get(3)?,
+                        ..Default::default()
+                    },
+                ))
+            },
+        )
+        .optional()?;
+
+    if hit.is_some() {
+        db.execute(
+            "UPDATE llm_cache SET hits = hits + 1, last_hit_at = ?
+            WHERE backend = ? AND model = ? AND system_prompt_hash = ? AND schema_hash = ?
+                AND input_hash = ?",
+            (now, params.0, params.1, params.2, params.3, params.4),
+        )?;
+    }
+
+    Ok(hit)
+}
+
+pub(crate) fn insert_cached_response(
+    db: &rusqlite::Connection,
+    key: &crate::cache::Key,
+    response: &str,
+    usage: &crate::openai::Usage,
+    now: i64,
+) -> anyhow::Result<()> {
+    db.execute(
+        "INSERT INTO llm_cache (backend, model, system_prompt_hash, schema_hash, input_hash,
+            response, prompt_tokens, completion_tokens, total_tokens, created_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        ON CONFLICT(backend, model, system_prompt_hash, schema_hash, input_hash) DO UPDATE SET
+            response = excluded.response,
+            prompt_tokens = excluded.prompt_tokens,
+            completion_tokens = excluded.completion_tokens,
+            total_tokens = excluded.total_tokens,
+            created_at = excluded.created_at",
+        rusqlite::params![
+            key.backend,
+            key.model,
+            key.system_prompt_hash,
+            key.schema_hash,
+            key.input_hash,
+            response,
+            usage.prompt_tokens,
+            usage.completion_tokens,
+            usage.total_tokens,
+            now,
+        ],
+    )?;
+    Ok(())
+}
+
+pub(crate) fn get_cache_stats(
+    db: &rusqlite::Connection,
+) -> anyhow::Result<Vec<crate::cache::Stats>> {
+    let mut stmt = db.prepare(
+        "SELECT backend, model, COUNT(*), SUM(hits), SUM(hits * total_tokens) FROM llm_cache
+        GROUP BY backend, model
+        ORDER BY backend, model",
+    )?;
+
+    let mut stats = Vec::new();
+    for row in stmt.query_map((), |row| {
+        Ok(crate::cache::Stats {
+            backend: row.get(0)?,
+            model: row.get(1)?,
+            entries: row.get(2)?,
+            hits: row.get(3)?,
+            tokens_saved: row.get(4)?,
+        })
+    })? {
+        stats.push(row?);
+    }
+    Ok(stats)
+}
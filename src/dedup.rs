@@ -0,0 +1,330 @@
+//! Detection of near-duplicate submissions before anything is summarized. The same announcement
+//! often shows up as several stories, from the blog post, a mirror and tracking links to it. They
+//! are clustered by canonical url and by a SimHash of the scraped text, and only the best voted
+//! story of each cluster goes on to the model. The others are kept on it to be listed as "also
+//! discussed at".
+
+/// Number of consecutive words hashed together into one feature of the SimHash.
+const SHINGLE_WORDS: usize = 3;
+
+/// Texts with fewer words are only compared by url, their SimHash is too noisy.
+const MIN_WORDS: usize = 50;
+
+/// Query parameters that only track where a click came from.
+const TRACKING_PARAMS: [&str; 8] = [
+    "ref", "ref_src", "source", "fbclid", "gclid", "mc_cid", "mc_eid", "share",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Mode {
+    /// Every story is summarized.
+    Off,
+    /// Stories with the same canonical url are merged.
+    Url,
+    /// Stories with the same canonical url or a SimHash of their text within
+    /// `DEDUP_MAX_DISTANCE` bits are merged.
+    Text,
+}
+
+impl std::str::FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "off" => Ok(Mode::Off),
+            "url" => Ok(Mode::Url),
+            "text" => Ok(Mode::Text),
+            _ => anyhow::bail!("Unknown dedup mode {s}, expected off, url or text"),
+        }
+    }
+}
+
+/// Another submission of the same story.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub(crate) struct Duplicate {
+    pub(crate) id: i64,
+    pub(crate) title: String,
+    pub(crate) url: Option<String>,
+}
+
+/// Url without scheme, `www.`, fragment, tracking parameters and trailing slash, so links to the
+/// same page compare equal.
+pub(crate) fn canonical_url(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).ok()?;
+    let host = url.host_str()?.to_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+
+    let path = url
+        .path()
+        .trim_end_matches("/index.html")
+        .trim_end_matches('/');
+
+    let mut params: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(key, _)| !key.starts_with("utm_") && !TRACKING_PARAMS.contains(&key.as_ref()))
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+    params.sort();
+    let query = params
+        .iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect::<Vec<_>>()
+        .join("&");
+
+    Some(if query.is_empty() {
+        format!("{host}{path}")
+    } else {
+        format!("{host}{path}?{query}")
+    })
+}
+
+/// 64 bit FNV-1a, stable across runs and platforms unlike the std hasher.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// SimHash of the text's word shingles. Texts that share most of their shingles differ in few
+/// bits. `None` for texts too short to tell.
+pub(crate) fn simhash(text: &str) -> Option<u64> {
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    if words.len() < MIN_WORDS {
+        return None;
+    }
+
+    let mut weights = [0i64; 64];
+    for shingle in words.windows(SHINGLE_WORDS) {
+        let hash = fnv1a(shingle.join(" ").as_bytes());
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            *weight += if hash >> bit & 1 == 1 { 1 } else { -1 };
+        }
+    }
+
+    Some(
+        weights
+            .iter()
+            .enumerate()
+            .filter(|(_, weight)| **weight > 0)
+            .fold(0, |hash, (bit, _)| hash | 1 << bit),
+    )
+}
+
+#[derive(Debug)]
+struct Fingerprint {
+    url: Option<String>,
+    simhash: Option<u64>,
+}
+
+impl Fingerprint {
+    fn new(story: &crate::Story, mode: Mode) -> Self {
+        Self {
+            url: story.url.as_deref().and_then(canonical_url),
+            simhash: match mode {
+                Mode::Text => story.text.as_deref().and_then(simhash),
+                Mode::Off | Mode::Url => None,
+            },
+        }
+    }
+
+    fn is_near_duplicate(&self, other: &Self, max_distance: u32) -> bool {
+        let same_url = self.url.is_some() && self.url == other.url;
+        let similar_text = match (self.simhash, other.simhash) {
+            (Some(a), Some(b)) => (a ^ b).count_ones() <= max_distance,
+            _ => false,
+        };
+        same_url || similar_text
+    }
+}
+
+fn find_root(parents: &mut [usize], i: usize) -> usize {
+    let mut root = i;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    parents[i] = root;
+    root
+}
+
+/// Merges near-duplicate stories into the best of each cluster, by triage score, votes and
+/// comments. The others are recorded as its duplicates and their sources are added to its own,
+/// so every profile that wanted one of them still gets the story.
+pub(crate) fn deduplicate(
+    stories: Vec<crate::Story>,
+    mode: Mode,
+    max_distance: u32,
+) -> Vec<crate::Story> {
+    if mode == Mode::Off {
+        return stories;
+    }
+
+    let fingerprints: Vec<Fingerprint> =
+        stories.iter().map(|s| Fingerprint::new(s, mode)).collect();
+    let mut parents: Vec<usize> = (0..stories.len()).collect();
+    for i in 0..stories.len() {
+        for j in i + 1..stories.len() {
+            if fingerprints[i].is_near_duplicate(&fingerprints[j], max_distance) {
+                let (a, b) = (find_root(&mut parents, i), find_root(&mut parents, j));
+                parents[b.max(a)] = a.min(b);
+            }
+        }
+    }
+
+    let mut clusters: std::collections::BTreeMap<usize, Vec<usize>> = Default::default();
+    for i in 0..stories.len() {
+        clusters
+            .entry(find_root(&mut parents, i))
+            .or_default()
+            .push(i);
+    }
+
+    let mut stories: Vec<Option<crate::Story>> = stories.into_iter().map(Some).collect();
+    let mut deduplicated = Vec::with_capacity(clusters.len());
+    for members in clusters.into_values() {
+        let best = *members
+            .iter()
+            .max_by_key(|i| {
+                let s = stories[**i].as_ref().expect("Story in one cluster");
+                (
+                    s.triage_score,
+                    s.score,
+                    s.descendants,
+                    std::cmp::Reverse(s.id),
+                )
+            })
+            .expect("Cluster has members");
+        let mut representative = stories[best].take().expect("Story in one cluster");
+
+        for i in members.into_iter().filter(|i| *i != best) {
+            let duplicate = stories[i].take().expect("Story in one cluster");
+            for source in duplicate.sources {
+                if !representative.sources.contains(&source) {
+                    representative.sources.push(source);
+                }
+            }
+            representative.duplicates.push(Duplicate {
+                id: duplicate.id,
+                title: duplicate.title,
+                url: duplicate.url,
+            });
+        }
+
+        if !representative.duplicates.is_empty() {
+            tracing::info!(
+                id = representative.id,
+                title = representative.title,
+                duplicates =? representative.duplicates,
+                "Merged near-duplicate stories"
+            );
+        }
+        deduplicated.push(representative);
+    }
+
+    deduplicated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "The lab released an open weights model today. It has seventy billion \
+        parameters and was trained on fifteen trillion tokens of public web data, code and \
+        books. On the common coding and reasoning benchmarks it matches the best closed models \
+        of last year while running on a single node. The weights are published under a \
+        permissive license that allows commercial use, and the training code and data recipe \
+        are released alongside them so that others can reproduce the results.";
+
+    fn story(id: i64, score: i64, url: &str, text: &str) -> crate::Story {
+        crate::Story {
+            id,
+            score,
+            title: format!("Story {id}"),
+            url: Some(url.to_string()),
+            text: Some(text.to_string()),
+            sources: vec![crate::profile::Source::Top],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_canonical_url() {
+        assert_eq!(
+            canonical_url("https://www.Example.com/post/?utm_source=hn&id=1&ref=x#comments"),
+            Some("example.com/post?id=1".to_string())
+        );
+        assert_eq!(
+            canonical_url("http://example.com/post/index.html"),
+            canonical_url("https://example.com/post")
+        );
+        assert_ne!(
+            canonical_url("https://example.com/post?id=1"),
+            canonical_url("https://example.com/post?id=2")
+        );
+        assert_eq!(canonical_url("not a url"), None);
+    }
+
+    #[test]
+    fn test_simhash() {
+        // Articles are longer than the test text, one edited sentence in a few paragraphs.
+        let article = [TEXT; 4].join("\n\n");
+        let original = simhash(&article).unwrap();
+        let edited = simhash(&article.replacen("today", "on Tuesday", 1)).unwrap();
+        let other = simhash(
+            "A database engine written in Rust reached its first stable release after five \
+            years of development. The release adds a query planner that reorders joins by their \
+            estimated cost, a write ahead log that survives power loss without corruption and \
+            a replication protocol for read replicas in other regions. Benchmarks on a laptop \
+            show it handling two hundred thousand inserts per second, and the authors plan to \
+            offer a hosted version of the engine next year.",
+        )
+        .unwrap();
+
+        assert!((original ^ edited).count_ones() <= 3);
+        assert!((original ^ other).count_ones() > 10);
+        assert_eq!(simhash("Too short to tell."), None);
+    }
+
+    #[test]
+    fn test_deduplicate() {
+        let mut mirror = story(2, 300, "https://mirror.example.org/a", TEXT);
+        mirror.sources = vec![crate::profile::Source::Show];
+        let stories = vec![
+            story(1, 100, "https://example.com/a?utm_source=hn", TEXT),
+            mirror,
+            story(3, 50, "https://example.com/a", "Short."),
+            story(4, 500, "https://other.example.com/b", "Short."),
+        ];
+
+        let deduplicated = deduplicate(stories.clone(), Mode::Text, 3);
+        assert_eq!(deduplicated.len(), 2);
+        assert_eq!(deduplicated[0].id, 2);
+        assert_eq!(
+            deduplicated[0]
+                .duplicates
+                .iter()
+                .map(|d| d.id)
+                .collect::<Vec<_>>(),
+            [1, 3]
+        );
+        assert_eq!(
+            deduplicated[0].sources,
+            [crate::profile::Source::Show, crate::profile::Source::Top]
+        );
+        assert_eq!(deduplicated[1].id, 4);
+
+        // Without text only the same url merges stories.
+        let deduplicated = deduplicate(stories.clone(), Mode::Url, 3);
+        assert_eq!(
+            deduplicated.iter().map(|s| s.id).collect::<Vec<_>>(),
+            [1, 2, 4]
+        );
+        assert_eq!(deduplicated[0].duplicates[0].id, 3);
+
+        assert_eq!(deduplicate(stories, Mode::Off, 3).len(), 4);
+    }
+}
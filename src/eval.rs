@@ -0,0 +1,438 @@
+//! Offline evaluation of prompts and models. A labeled dataset of stories is classified by every
+//! combination of model and system prompt, and each combination is scored against the expected
+//! labels of one topic. The model calls go through the usual cassette and response cache, so an
+//! evaluation can be replayed from a recording or run against a local stand-in at
+//! `OPENAI_BASE_URL` without billing anything.
+
+/// One labeled story of the dataset.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Example {
+    pub(crate) title: String,
+    pub(crate) url: Option<String>,
+    pub(crate) text: String,
+    /// Expected label for the evaluated topic.
+    pub(crate) label: String,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub(crate) struct Prediction {
+    pub(crate) title: String,
+    pub(crate) expected: String,
+    /// None if the model call failed.
+    pub(crate) predicted: Option<String>,
+}
+
+/// Results of one model and prompt combination.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub(crate) struct Result {
+    pub(crate) model: String,
+    /// File the system prompt was read from, or `default` for the profile's prompt.
+    pub(crate) prompt: String,
+    pub(crate) labels: Vec<String>,
+    /// Expected labels as rows and predicted labels as columns, in the order of `labels`.
+    pub(crate) confusion_matrix: Vec<Vec<usize>>,
+    pub(crate) accuracy: f64,
+    /// Precision and recall of the most relevant label. None if there is nothing to divide by.
+    pub(crate) precision: Option<f64>,
+    pub(crate) recall: Option<f64>,
+    pub(crate) errors: usize,
+    pub(crate) usage: crate::openai::Usage,
+    pub(crate) cost: f64,
+    pub(crate) predictions: Vec<Prediction>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub(crate) struct Report {
+    pub(crate) topic: String,
+    pub(crate) results: Vec<Result>,
+}
+
+pub(crate) fn load_dataset(path: &str) -> anyhow::Result<Vec<Example>> {
+    let json = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Error reading dataset {path}: {e}"))?;
+    let examples: Vec<Example> = serde_json::from_str(&json)?;
+    if examples.is_empty() {
+        anyhow::bail!("Dataset {path} has no examples");
+    }
+    Ok(examples)
+}
+
+/// Confusion matrix, accuracy and precision and recall of the first label. Failed predictions
+/// count as wrong but do not show up in the matrix.
+fn score(
+    labels: &[String],
+    predictions: &[Prediction],
+) -> (Vec<Vec<usize>>, f64, Option<f64>, Option<f64>) {
+    let mut matrix = vec![vec![0; labels.len()]; labels.len()];
+    for prediction in predictions {
+        let expected = labels.iter().position(|l| *l == prediction.expected);
+        let predicted = prediction
+            .predicted
+            .as_ref()
+            .and_then(|p| labels.iter().position(|l| l == p));
+        if let (Some(expected), Some(predicted)) = (expected, predicted) {
+            matrix[expected][predicted] += 1;
+        }
+    }
+
+    let correct: usize = (0..labels.len()).map(|i| matrix[i][i]).sum();
+    let accuracy = if predictions.is_empty() {
+        0.0
+    } else {
+        correct as f64 / predictions.len() as f64
+    };
+
+    let ratio = |n: usize, d: usize| (d > 0).then(|| n as f64 / d as f64);
+    let true_positives = matrix[0][0];
+    let predicted_positives: usize = matrix.iter().map(|row| row[0]).sum();
+    let expected_positives = predictions
+        .iter()
+        .filter(|p| p.expected == labels[0])
+        .count();
+
+    (
+        matrix,
+        accuracy,
+        ratio(true_positives, predicted_positives),
+        ratio(true_positives, expected_positives),
+    )
+}
+
+async fn evaluate(
+    examples: &[Example],
+    profile: std::sync::Arc<crate::profile::Profile>,
+    topic: &crate::topics::Topic,
+    prompt: &str,
+) -> Result {
+    let mut join_set = tokio::task::JoinSet::new();
+    for (index, example) in examples.iter().enumerate() {
+        let story = crate::Story {
+            id: index as i64,
+            title: example.title.clone(),
+            url: example.url.clone(),
+            text: Some(example.text.clone()),
+            ..Default::default()
+        };
+        let profile = profile.clone();
+        join_set.spawn(async move { (index, crate::openai::enrich_story(story, &profile).await) });
+    }
+
+    let mut predicted = vec![None; examples.len()];
+    let mut usage = crate::openai::Usage::default();
+    let mut usage_by_model = std::collections::BTreeMap::new();
+    while let Some(joined) = join_set.join_next().await {
+        let (index, result) = match joined {
+            Ok(joined) => joined,
+            Err(e) => {
+                tracing::error!(error =? e, "Evaluation task panicked");
+                continue;
+            }
+        };
+        match result {
+            Ok(story) => {
+                usage += story.usage.as_ref().unwrap_or(&Default::default());
+                for (model, model_usage) in &story.usage_by_model {
+                    *usage_by_model.entry(model.clone()).or_default() += model_usage;
+                }
+                predicted[index] = story.classifications.and_then(|classifications| {
+                    classifications
+                        .into_iter()
+                        .find(|c| c.topic == topic.name)
+                        .map(|c| c.label)
+                });
+            }
+            Err(e) => {
+                tracing::warn!(error =? e, title = examples[index].title, "Error evaluating example");
+            }
+        }
+    }
+
+    let predictions: Vec<Prediction> = examples
+        .iter()
+        .zip(predicted)
+        .map(|(example, predicted)| Prediction {
+            title: example.title.clone(),
+            expected: example.label.clone(),
+            predicted,
+        })
+        .collect();
+    let (confusion_matrix, accuracy, precision, recall) = score(&topic.labels, &predictions);
+
+    Result {
+        model: profile.model.clone(),
+        prompt: prompt.to_string(),
+        labels: topic.labels.clone(),
+        confusion_matrix,
+        accuracy,
+        precision,
+        recall,
+        errors: predictions.iter().filter(|p| p.predicted.is_none()).count(),
+        cost: crate::tokens::total_cost(&usage_by_model),
+        usage,
+        predictions,
+    }
+}
+
+/// Classifies the dataset with every combination of `models` and `prompts`. Without models or
+/// prompts the profile's own are used. A given model is the only one that votes, in place of the
+/// profile's consensus models.
+pub(crate) async fn eval(
+    examples: &[Example],
+    profile: &crate::profile::Profile,
+    topic: Option<&str>,
+    models: &[String],
+    prompts: &[String],
+) -> anyhow::Result<Report> {
+    let topic = match topic {
+        Some(name) => profile
+            .topics
+            .iter()
+            .find(|t| t.name == name)
+            .ok_or_else(|| anyhow::anyhow!("Profile {} has no topic {name}", profile.name))?,
+        None => &profile.topics[0],
+    };
+    if let Some(example) = examples.iter().find(|e| !topic.labels.contains(&e.label)) {
+        anyhow::bail!(
+            "Label {} of example {:?} is not a label of topic {}",
+            example.label,
+            example.title,
+            topic.name
+        );
+    }
+
+    let (models, consensus_models) = if models.is_empty() {
+        (
+            std::slice::from_ref(&profile.model),
+            &profile.consensus_models[..],
+        )
+    } else {
+        (models, &[][..])
+    };
+    let mut system_prompts = vec![("default".to_string(), profile.system_prompt.clone())];
+    if !prompts.is_empty() {
+        system_prompts = prompts
+            .iter()
+            .map(|path| {
+                let prompt = crate::prompt::load(path)?;
+                crate::prompt::check_templates(&prompt, &profile.user_prompt)
+                    .map_err(|e| anyhow::anyhow!("Prompt {path}: {e}"))?;
+                Ok((path.clone(), prompt))
+            })
+            .collect::<anyhow::Result<_>>()?;
+    }
+
+    let mut results = Vec::with_capacity(models.len() * system_prompts.len());
+    for model in models {
+        for (prompt, system_prompt) in &system_prompts {
+            // The model calls run in spawned tasks, which share the profile.
+            let variant = std::sync::Arc::new(crate::profile::Profile {
+                model: model.clone(),
+                consensus_models: consensus_models.to_vec(),
+                system_prompt: system_prompt.clone(),
+                ..profile.clone()
+            });
+            tracing::info!(model, prompt, "Evaluating");
+            results.push(evaluate(examples, variant, topic, prompt).await);
+        }
+    }
+
+    Ok(Report {
+        topic: topic.name.clone(),
+        results,
+    })
+}
+
+pub(crate) fn load_report(path: &str) -> anyhow::Result<Report> {
+    let json = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Error reading baseline {path}: {e}"))?;
+    Ok(serde_json::from_str(&json)?)
+}
+
+/// Examples whose prediction differs between the baseline and the result, as
+/// (title, baseline prediction, new prediction).
+fn changed_predictions<'a>(
+    baseline: &'a Result,
+    result: &'a Result,
+) -> Vec<(&'a str, Option<&'a str>, Option<&'a str>)> {
+    result
+        .predictions
+        .iter()
+        .filter_map(|prediction| {
+            let before = baseline
+                .predictions
+                .iter()
+                .find(|p| p.title == prediction.title)?;
+            (before.predicted != prediction.predicted).then_some((
+                prediction.title.as_str(),
+                before.predicted.as_deref(),
+                prediction.predicted.as_deref(),
+            ))
+        })
+        .collect()
+}
+
+fn format_ratio(ratio: Option<f64>) -> String {
+    ratio.map_or("-".to_string(), |r| format!("{r:.2}"))
+}
+
+fn format_delta(new: Option<f64>, old: Option<f64>) -> String {
+    match (new, old) {
+        (Some(new), Some(old)) => format!("{:+.2}", new - old),
+        _ => "-".to_string(),
+    }
+}
+
+pub(crate) fn print_report(
+    report: &Report,
+    baseline: Option<&Report>,
+    json: bool,
+) -> anyhow::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(report)?);
+        return Ok(());
+    }
+
+    for result in &report.results {
+        println!("Model {}, prompt {}", result.model, result.prompt);
+        print!("{:<12}", "Expected");
+        for label in &result.labels {
+            print!(" {label:>8}");
+        }
+        println!();
+        for (label, row) in result.labels.iter().zip(&result.confusion_matrix) {
+            print!("{label:<12}");
+            for count in row {
+                print!(" {count:>8}");
+            }
+            println!();
+        }
+        println!(
+            "Accuracy {:.2}, {} precision {}, recall {}, errors {}, {} tokens, {} cached, ${:.4}",
+            result.accuracy,
+            result.labels[0],
+            format_ratio(result.precision),
+            format_ratio(result.recall),
+            result.errors,
+            result.usage.total_tokens,
+            result.usage.cached_tokens,
+            result.cost
+        );
+
+        let Some(before) = baseline.and_then(|b| {
+            b.results
+                .iter()
+                .find(|r| r.model == result.model && r.prompt == result.prompt)
+        }) else {
+            println!();
+            continue;
+        };
+        println!(
+            "Against baseline: accuracy {:+.2}, precision {}, recall {}, cost {:+.4}",
+            result.accuracy - before.accuracy,
+            format_delta(result.precision, before.precision),
+            format_delta(result.recall, before.recall),
+            result.cost - before.cost
+        );
+        for (title, old, new) in changed_predictions(before, result) {
+            println!(
+                "  {title}: {} -> {}",
+                old.unwrap_or("error"),
+                new.unwrap_or("error")
+            );
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prediction(title: &str, expected: &str, predicted: Option<&str>) -> Prediction {
+        Prediction {
+            title: title.to_string(),
+            expected: expected.to_string(),
+            predicted: predicted.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_score() {
+        let labels: Vec<String> = ["High", "Medium", "Low"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let predictions = [
+            prediction("a", "High", Some("High")),
+            prediction("b", "High", Some("Medium")),
+            prediction("c", "Medium", Some("High")),
+            prediction("d", "Low", Some("Low")),
+            prediction("e", "High", None),
+        ];
+
+        let (matrix, accuracy, precision, recall) = score(&labels, &predictions);
+        assert_eq!(matrix, vec![vec![1, 1, 0], vec![1, 0, 0], vec![0, 0, 1]]);
+        assert_eq!(accuracy, 0.4);
+        assert_eq!(precision, Some(0.5));
+        assert_eq!(recall, Some(1.0 / 3.0));
+
+        let (_, _, precision, recall) = score(&labels, &[prediction("a", "Low", Some("Low"))]);
+        assert_eq!(precision, None);
+        assert_eq!(recall, None);
+    }
+
+    #[test]
+    fn test_changed_predictions() {
+        let result = |predictions: Vec<Prediction>| Result {
+            model: "gpt-4o-mini".to_string(),
+            prompt: "default".to_string(),
+            labels: vec!["High".to_string(), "Low".to_string()],
+            confusion_matrix: Vec::new(),
+            accuracy: 0.0,
+            precision: None,
+            recall: None,
+            errors: 0,
+            usage: Default::default(),
+            cost: 0.0,
+            predictions,
+        };
+        let baseline = result(vec![
+            prediction("a", "High", Some("High")),
+            prediction("b", "Low", Some("High")),
+            prediction("c", "Low", None),
+        ]);
+        let new = result(vec![
+            prediction("a", "High", Some("High")),
+            prediction("b", "Low", Some("Low")),
+            prediction("c", "Low", Some("Low")),
+            prediction("d", "Low", Some("Low")),
+        ]);
+
+        assert_eq!(
+            changed_predictions(&baseline, &new),
+            vec![("b", Some("High"), Some("Low")), ("c", None, Some("Low"))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replay_eval() {
+        assert_eq!(
+            crate::config::config().cassette_mode,
+            crate::cassette::Mode::Replay
+        );
+
+        let examples = load_dataset("tests/fixtures/eval/dataset.json").unwrap();
+        let profile = &crate::config::config().profiles[0];
+        let report = eval(&examples, profile, None, &[], &[]).await.unwrap();
+
+        assert_eq!(report.topic, "AI");
+        let result = &report.results[0];
+        assert_eq!(result.errors, 0);
+        assert_eq!(result.confusion_matrix.iter().flatten().sum::<usize>(), 3);
+        assert_eq!(result.usage.total_tokens, 3600);
+        assert_eq!(result.recall, Some(1.0));
+    }
+}
@@ -0,0 +1,114 @@
+//! What a profile's digest does on a day when no story meets the threshold. A silent channel
+//! looks like a broken bot, so by default a short note with the closest titles is sent instead.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Policy {
+    /// Nothing is sent.
+    Skip,
+    /// The best stories one label below the threshold are sent as the digest.
+    Promote,
+    /// A short note listing the titles of the best scored stories is sent.
+    Quiet,
+}
+
+impl std::str::FromStr for Policy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "skip" => Ok(Policy::Skip),
+            "promote" => Ok(Policy::Promote),
+            "quiet" => Ok(Policy::Quiet),
+            _ => anyhow::bail!("Unknown fallback policy {s}, expected skip, promote or quiet"),
+        }
+    }
+}
+
+impl std::fmt::Display for Policy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Policy::Skip => write!(f, "skip"),
+            Policy::Promote => write!(f, "promote"),
+            Policy::Quiet => write!(f, "quiet"),
+        }
+    }
+}
+
+/// A fallback applied to a profile's digest in a run.
+#[derive(Debug, Clone)]
+pub(crate) struct Fallback {
+    pub(crate) profile: String,
+    pub(crate) policy: Policy,
+    /// Stories promoted or listed in the note.
+    pub(crate) num_stories: usize,
+}
+
+/// Up to `max` of the ranked stories that missed the threshold by one label and have a summary.
+pub(crate) fn promote(
+    topics: &[crate::topics::Topic],
+    ranked: &[crate::Story],
+    max: usize,
+) -> Vec<crate::Story> {
+    ranked
+        .iter()
+        .filter(|s| {
+            s.summary.is_some()
+                && s.classifications
+                    .as_deref()
+                    .is_some_and(|c| crate::topics::is_near_miss(topics, c))
+        })
+        .take(max)
+        .cloned()
+        .collect()
+}
+
+/// Up to `max` of the ranked stories to list in a quiet day note.
+pub(crate) fn quiet(ranked: &[crate::Story], max: usize) -> Vec<crate::Story> {
+    ranked
+        .iter()
+        .filter(|s| s.url.is_some() && s.classifications.is_some())
+        .take(max)
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn story(id: i64, label: &str, summarized: bool) -> crate::Story {
+        let topics = crate::topics::default_topics();
+        let labels = [(topics[0].name.clone(), label.to_string())].into();
+        crate::Story {
+            id,
+            url: Some(format!("https://example.com/{id}")),
+            classifications: Some(crate::topics::classify(&topics, &labels).unwrap()),
+            summary: summarized.then(|| crate::openai::Summary {
+                tldr: "A model was released.".to_string(),
+                key_points: Vec::new(),
+                why_it_matters: "It is open.".to_string(),
+                content_type: crate::openai::ContentType::News,
+                entities: Default::default(),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_promote_and_quiet() {
+        let topics = crate::topics::default_topics();
+        let ranked = [
+            story(0, "Medium", false),
+            story(1, "Medium", true),
+            story(2, "Medium", true),
+            story(3, "Medium", true),
+            story(4, "Low", true),
+        ];
+
+        let ids = |stories: Vec<crate::Story>| stories.iter().map(|s| s.id).collect::<Vec<_>>();
+        assert_eq!(ids(promote(&topics, &ranked, 2)), [1, 2]);
+        assert_eq!(ids(promote(&topics, &ranked[4..], 2)), Vec::<i64>::new());
+        assert_eq!(ids(quiet(&ranked, 3)), [0, 1, 2]);
+    }
+}
@@ -0,0 +1,229 @@
+//! Rule based filters applied right after fetching, before anything costs tokens. Obvious junk is
+//! dropped by domain, title, votes or age, and stories from "always include" domains are let
+//! through every filter and triage. The rule that decided is kept for every story it matched.
+
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub(crate) struct Rules {
+    /// Stories from these domains or their subdomains pass every other rule and triage.
+    pub(crate) always_include_domains: Vec<String>,
+    /// When not empty, only stories from these domains or their subdomains are kept.
+    pub(crate) allow_domains: Vec<String>,
+    pub(crate) deny_domains: Vec<String>,
+    /// Titles have to match this, when set.
+    #[serde(serialize_with = "serialize_regex")]
+    pub(crate) title_include: Option<regex::Regex>,
+    /// Titles matching this are dropped.
+    #[serde(serialize_with = "serialize_regex")]
+    pub(crate) title_exclude: Option<regex::Regex>,
+    pub(crate) min_points: i64,
+    pub(crate) min_comments: i64,
+    /// Stories submitted longer ago are dropped. Stories without a submission time are kept.
+    pub(crate) max_age_hours: Option<u64>,
+}
+
+fn serialize_regex<S: serde::Serializer>(
+    regex: &Option<regex::Regex>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_some(&regex.as_ref().map(regex::Regex::as_str))
+}
+
+/// Outcome of the rules for one story.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Verdict {
+    /// Kept by an "always include" rule.
+    Include(String),
+    Exclude(String),
+    /// No rule matched, the story is kept.
+    Pass,
+}
+
+/// A rule that matched a story in a run.
+#[derive(Debug, Clone)]
+pub(crate) struct Match {
+    pub(crate) story_id: i64,
+    pub(crate) rule: String,
+    pub(crate) included: bool,
+}
+
+/// Parses a comma separated list of domains.
+pub(crate) fn parse_domains(s: &str) -> Vec<String> {
+    s.split(',')
+        .map(|d| d.trim().trim_start_matches("www.").to_lowercase())
+        .filter(|d| !d.is_empty())
+        .collect()
+}
+
+/// The listed domain the story's domain is, or is a subdomain of.
+fn find_domain<'a>(domain: &str, domains: &'a [String]) -> Option<&'a str> {
+    domains
+        .iter()
+        .find(|d| domain == d.as_str() || domain.ends_with(&format!(".{d}")))
+        .map(String::as_str)
+}
+
+/// Applies the rules in order: always include, deny, allow, title exclude, title include, points,
+/// comments and age. The first rule that decides wins.
+pub(crate) fn check(story: &crate::Story, rules: &Rules, now: i64) -> Verdict {
+    let domain = story
+        .url
+        .as_deref()
+        .and_then(crate::triage::domain)
+        .unwrap_or_default();
+
+    if let Some(d) = find_domain(&domain, &rules.always_include_domains) {
+        return Verdict::Include(format!("always_include_domains: {d}"));
+    }
+    if let Some(d) = find_domain(&domain, &rules.deny_domains) {
+        return Verdict::Exclude(format!("deny_domains: {d}"));
+    }
+    if !rules.allow_domains.is_empty() && find_domain(&domain, &rules.allow_domains).is_none() {
+        return Verdict::Exclude(format!("allow_domains: {domain} not listed"));
+    }
+    if let Some(regex) = &rules.title_exclude
+        && regex.is_match(&story.title)
+    {
+        return Verdict::Exclude(format!("title_exclude: {}", regex.as_str()));
+    }
+    if let Some(regex) = &rules.title_include
+        && !regex.is_match(&story.title)
+    {
+        return Verdict::Exclude(format!("title_include: {}", regex.as_str()));
+    }
+    if story.score < rules.min_points {
+        return Verdict::Exclude(format!("min_points: {}", rules.min_points));
+    }
+    if story.descendants.unwrap_or_default() < rules.min_comments {
+        return Verdict::Exclude(format!("min_comments: {}", rules.min_comments));
+    }
+    if let Some(max_age_hours) = rules.max_age_hours
+        && let Some(time) = story.time
+        && now - time > max_age_hours as i64 * 60 * 60
+    {
+        return Verdict::Exclude(format!("max_age_hours: {max_age_hours}"));
+    }
+    Verdict::Pass
+}
+
+/// Drops the stories a rule excludes and marks the ones a rule always includes. Every match is
+/// logged and recorded on the run.
+pub(crate) fn apply(
+    stories: Vec<crate::Story>,
+    rules: &Rules,
+    now: i64,
+    run: &mut crate::run::Run,
+) -> Vec<crate::Story> {
+    let mut kept = Vec::with_capacity(stories.len());
+    for mut story in stories {
+        match check(&story, rules, now) {
+            Verdict::Include(rule) => {
+                tracing::info!(title = story.title, rule = rule, "Always including story");
+                run.filter_matches.push(Match {
+                    story_id: story.id,
+                    rule: rule.clone(),
+                    included: true,
+                });
+                story.filter_rule = Some(rule);
+                kept.push(story);
+            }
+            Verdict::Exclude(rule) => {
+                tracing::info!(title = story.title, rule = rule, "Filtered out story");
+                run.filter_matches.push(Match {
+                    story_id: story.id,
+                    rule,
+                    included: false,
+                });
+            }
+            Verdict::Pass => kept.push(story),
+        }
+    }
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_760_745_600;
+
+    fn story(title: &str, url: &str, score: i64, descendants: i64, age_hours: i64) -> crate::Story {
+        crate::Story {
+            title: title.to_string(),
+            url: Some(url.to_string()),
+            score,
+            descendants: Some(descendants),
+            time: Some(NOW - age_hours * 60 * 60),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let rules = Rules {
+            always_include_domains: parse_domains("openai.com, www.arxiv.org"),
+            deny_domains: parse_domains("spam.example"),
+            title_include: None,
+            title_exclude: Some(regex::Regex::new("(?i)^(ask|tell) hn").unwrap()),
+            min_points: 10,
+            min_comments: 2,
+            max_age_hours: Some(48),
+            ..Default::default()
+        };
+        let verdict = |story: &crate::Story| check(story, &rules, NOW);
+
+        assert_eq!(
+            verdict(&story("A model", "https://example.com", 50, 10, 1)),
+            Verdict::Pass
+        );
+        // Always included stories skip every other rule.
+        assert_eq!(
+            verdict(&story(
+                "Ask HN: a paper",
+                "https://export.arxiv.org/abs/1",
+                1,
+                0,
+                100
+            )),
+            Verdict::Include("always_include_domains: arxiv.org".to_string())
+        );
+        assert_eq!(
+            verdict(&story("A model", "https://blog.spam.example", 50, 10, 1)),
+            Verdict::Exclude("deny_domains: spam.example".to_string())
+        );
+        assert_eq!(
+            verdict(&story("ask HN: models?", "https://example.com", 50, 10, 1)),
+            Verdict::Exclude("title_exclude: (?i)^(ask|tell) hn".to_string())
+        );
+        assert_eq!(
+            verdict(&story("A model", "https://example.com", 5, 10, 1)),
+            Verdict::Exclude("min_points: 10".to_string())
+        );
+        assert_eq!(
+            verdict(&story("A model", "https://example.com", 50, 1, 1)),
+            Verdict::Exclude("min_comments: 2".to_string())
+        );
+        assert_eq!(
+            verdict(&story("A model", "https://example.com", 50, 10, 49)),
+            Verdict::Exclude("max_age_hours: 48".to_string())
+        );
+
+        let rules = Rules {
+            allow_domains: parse_domains("example.com"),
+            title_include: Some(regex::Regex::new("(?i)model").unwrap()),
+            ..Default::default()
+        };
+        let verdict = |story: &crate::Story| check(story, &rules, NOW);
+        assert_eq!(
+            verdict(&story("A model", "https://blog.example.com", 0, 0, 1)),
+            Verdict::Pass
+        );
+        assert_eq!(
+            verdict(&story("A model", "https://notexample.com", 0, 0, 1)),
+            Verdict::Exclude("allow_domains: notexample.com not listed".to_string())
+        );
+        assert_eq!(
+            verdict(&story("A database", "https://example.com", 0, 0, 1)),
+            Verdict::Exclude("title_include: (?i)model".to_string())
+        );
+    }
+}
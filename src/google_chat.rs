@@ -3,12 +3,20 @@ struct Message {
     text: String,
 }
 
-fn story_to_message(story: &crate::Story) -> anyhow::Result<String> {
+fn story_to_message(
+    story: &crate::Story,
+    profile: &crate::profile::Profile,
+) -> anyhow::Result<String> {
     let crate::Story {
         title,
         url,
         summary,
-        ai_impact_score,
+        classifications,
+        relevance_score,
+        rationale,
+        confidence,
+        consensus,
+        source_language,
         score,
         id,
         descendants: descendents,
@@ -19,29 +27,162 @@ fn story_to_message(story: &crate::Story) -> anyhow::Result<String> {
     let descendents = descendents
         .as_ref()
         .ok_or(anyhow::anyhow!("descendents to be set"))?;
-    let ai_impact_score = ai_impact_score
+    let classifications = classifications
         .as_ref()
-        .ok_or(anyhow::anyhow!("ai impact score to be set"))?;
-    let summary = summary
-        .as_ref()
-        .ok_or(anyhow::anyhow!("summary to be set"))?
-        .to_vec()
-        .join("\n\n");
+        .ok_or(anyhow::anyhow!("classifications to be set"))?
+        .iter()
+        .map(|c| format!("{}: {}", c.topic, c.label))
+        .collect::<Vec<_>>()
+        .join(" | ");
+    let summary = summary_to_message(
+        summary
+            .as_ref()
+            .ok_or(anyhow::anyhow!("summary to be set"))?,
+    );
+
+    let consensus = match consensus {
+        Some(consensus) if crate::consensus::is_low(Some(*consensus)) => {
+            format!(
+                " | Low consensus ({:.0}% of votes agree)",
+                consensus * 100.0
+            )
+        }
+        _ => String::new(),
+    };
+
+    let groundedness = match story.groundedness {
+        Some(groundedness) => format!(", groundedness {groundedness:.2}"),
+        None => String::new(),
+    };
+    let translated = match source_language {
+        Some(source) if *source != profile.language => whatlang::Lang::from_code(source)
+            .map(|source| format!("_Translated from {}_\n", source.eng_name()))
+            .unwrap_or_default(),
+        _ => String::new(),
+    };
+
+    let also_discussed_at = if story.duplicates.is_empty() {
+        String::new()
+    } else {
+        let links = story
+            .duplicates
+            .iter()
+            .map(|d| {
+                let url = d
+                    .url
+                    .clone()
+                    .unwrap_or_else(|| format!("https://news.ycombinator.com/item?id={}", d.id));
+                let domain = crate::triage::domain(&url).unwrap_or_else(|| d.title.clone());
+                format!("<{url}|{domain}>")
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("\n_Also discussed at {links}_")
+    };
+
+    let rationale = match (
+        profile.show_rationale,
+        relevance_score,
+        rationale,
+        confidence,
+    ) {
+        (true, Some(relevance_score), Some(rationale), Some(confidence)) => format!(
+            "_Relevance {relevance_score}/100 (confidence {confidence:.2}{groundedness}): {rationale}_\n\n"
+        ),
+        _ => String::new(),
+    };
 
     Ok(format!(
-        "*<{url}|{title}>*\nAI Impact: {ai_impact_score} | Votes: {score} | <https://news.ycombinator.com/item?id={id}|{descendents} Comments>\n\n{summary}\n\n"
+        "*<{url}|{title}>*\n{classifications}{consensus} | Votes: {score} | <https://news.ycombinator.com/item?id={id}|{descendents} Comments>\n\n{rationale}{translated}{summary}{also_discussed_at}\n\n"
     ))
 }
 
-pub(crate) fn create_message(stories: Vec<crate::Story>) -> anyhow::Result<String> {
+fn summary_to_message(summary: &crate::openai::Summary) -> String {
+    let crate::openai::Summary {
+        tldr,
+        key_points,
+        why_it_matters,
+        content_type,
+        entities,
+    } = summary;
+
+    let mut message = format!("*{content_type}:* {tldr}\n");
+    for point in key_points {
+        message.push_str(&format!("• {point}\n"));
+    }
+    message.push_str(&format!("*Why it matters:* {why_it_matters}"));
+
+    let entities = [
+        ("Companies", &entities.companies),
+        ("Models", &entities.models),
+        ("People", &entities.people),
+        ("Products", &entities.products),
+    ]
+    .into_iter()
+    .filter(|(_, names)| !names.is_empty())
+    .map(|(kind, names)| format!("{kind}: {}", names.join(", ")))
+    .collect::<Vec<_>>();
+    if !entities.is_empty() {
+        message.push_str(&format!("\n_{}_", entities.join(" | ")));
+    }
+
+    message
+}
+
+/// Line of a quiet day note: the linked title and its labels.
+fn story_to_quiet_day_line(story: &crate::Story) -> anyhow::Result<String> {
+    let url = story.url.as_ref().ok_or(anyhow::anyhow!("url to be set"))?;
+    let labels = story
+        .classifications
+        .as_ref()
+        .ok_or(anyhow::anyhow!("classifications to be set"))?
+        .iter()
+        .map(|c| format!("{}: {}", c.topic, c.label))
+        .collect::<Vec<_>>()
+        .join(" | ");
+    Ok(format!("• <{url}|{}> ({labels})\n", story.title))
+}
+
+pub(crate) fn create_message(
+    profile: &crate::profile::Profile,
+    stories: &[crate::Story],
+    num_skipped: usize,
+    fallback: Option<crate::fallback::Policy>,
+) -> anyhow::Result<String> {
     let mut message = String::new();
-    message.push_str(&format!(
-        "*Daily digest of top Hacker news AI stories as per {}*\n\n",
-        crate::config::config().model
-    ));
+    let topics = profile
+        .topics
+        .iter()
+        .map(|t| t.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let model = &profile.model;
+
+    if fallback == Some(crate::fallback::Policy::Quiet) {
+        message.push_str(&format!(
+            "*Quiet day for Hacker news {topics} stories as per {model}*\n\n\
+            No story met the threshold today. These came closest:\n"
+        ));
+        for story in stories {
+            message.push_str(&story_to_quiet_day_line(story)?);
+        }
+        message.push('\n');
+    } else {
+        message.push_str(&format!(
+            "*Daily digest of top Hacker news {topics} stories as per {model}*\n\n"
+        ));
+        if fallback == Some(crate::fallback::Policy::Promote) {
+            message.push_str("_No story met the threshold today. These came closest:_\n\n");
+        }
+        for story in stories {
+            message.push_str(&story_to_message(story, profile)?);
+        }
+    }
 
-    for story in stories {
-        message.push_str(&story_to_message(&story)?);
+    if num_skipped > 0 {
+        message.push_str(&format!(
+            "_{num_skipped} stories were skipped because the run ran out of time._\n\n"
+        ));
     }
 
     const GITHUB_REPO_URL: &str = "https://github.com/mathiaskindberg/ai-summarizer";
@@ -51,13 +192,9 @@ pub(crate) fn create_message(stories: Vec<crate::Story>) -> anyhow::Result<Strin
 }
 
 pub(crate) async fn send_message(message: String, url: &str) -> anyhow::Result<()> {
-    let res = crate::CLIENT
-        .post(url)
-        .json(&Message { text: message })
-        .send()
-        .await?;
-
-    res.error_for_status()?;
+    crate::cassette::send(crate::CLIENT.post(url).json(&Message { text: message }))
+        .await?
+        .error_for_status()?;
 
     Ok(())
 }
@@ -0,0 +1,342 @@
+//! Verification of summaries against the scraped text. Every sentence of the tl;dr and the key
+//! points is checked for support in `Story::text`, first by word overlap and optionally by a
+//! second model call for the sentences the overlap check rejects. Unsupported sentences are
+//! dropped or marked, and the share of supported sentences is kept as the groundedness score.
+//! The "why it matters" sentence is an assessment rather than a claim about the text, so it is
+//! not checked. Summaries translated from another language share almost no words with the text,
+//! so they skip the overlap check and only the model can verify them.
+
+/// Words too common to count as evidence that a sentence is taken from the text.
+const STOPWORDS: [&str; 40] = [
+    "the", "and", "for", "are", "but", "not", "you", "all", "any", "can", "had", "her", "was",
+    "one", "our", "out", "has", "his", "how", "its", "new", "now", "see", "who", "did", "get",
+    "may", "use", "this", "that", "with", "from", "they", "will", "have", "into", "than", "more",
+    "their", "which",
+];
+
+/// Words are compared on this many leading characters, so "releases" and "released" match.
+const STEM_CHARS: usize = 6;
+
+/// Put in front of sentences that could not be verified in mark mode.
+const UNVERIFIED_MARKER: &str = "[unverified] ";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Mode {
+    /// Summaries are sent as the model wrote them.
+    Off,
+    /// Sentences need `GROUNDING_MIN_OVERLAP` of their words to appear in the text.
+    Lexical,
+    /// Sentences failing the overlap check are judged by `GROUNDING_MODEL`.
+    Model,
+}
+
+impl std::str::FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "off" => Ok(Mode::Off),
+            "lexical" => Ok(Mode::Lexical),
+            "model" => Ok(Mode::Model),
+            _ => anyhow::bail!("Unknown grounding mode {s}, expected off, lexical or model"),
+        }
+    }
+}
+
+/// What happens to unsupported sentences.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Action {
+    /// Unsupported key points are removed. The tl;dr cannot be removed and is marked instead.
+    Drop,
+    /// Unsupported sentences are prefixed with `[unverified]`.
+    Mark,
+}
+
+impl std::str::FromStr for Action {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "drop" => Ok(Action::Drop),
+            "mark" => Ok(Action::Mark),
+            _ => anyhow::bail!("Unknown grounding action {s}, expected drop or mark"),
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct GroundingResponse {
+    #[schemars(required)]
+    #[schemars(description = "One verdict for every numbered sentence")]
+    verdicts: Vec<Verdict>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Verdict {
+    #[schemars(required)]
+    #[schemars(description = "Number of the sentence")]
+    sentence: i64,
+
+    #[schemars(required)]
+    #[schemars(description = "Whether the text states or directly implies the sentence")]
+    supported: bool,
+}
+
+fn schema() -> crate::openai::Schema {
+    crate::openai::Schema {
+        name: "grounding_verdicts".to_string(),
+        schema: crate::openai::schema_for::<GroundingResponse>(),
+        strict: true,
+    }
+}
+
+const SYSTEM_PROMPT: &str = "You check summaries for claims that are not in the source. For \
+    every numbered sentence, decide whether the text states or directly implies it. Paraphrases \
+    are supported, added facts, numbers or names that the text does not contain are not.";
+
+/// Lowercased word stems of `text` that carry meaning.
+fn stems(text: &str) -> std::collections::HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .map(str::to_lowercase)
+        .filter(|w| (w.len() >= 3 && !STOPWORDS.contains(&w.as_str())) || w.parse::<f64>().is_ok())
+        .map(|w| w.chars().take(STEM_CHARS).collect())
+        .collect()
+}
+
+/// Share of the sentence's word stems found in the text. Sentences without any count as fully
+/// covered.
+pub(crate) fn overlap(sentence: &str, text_stems: &std::collections::HashSet<String>) -> f64 {
+    let sentence_stems = stems(sentence);
+    if sentence_stems.is_empty() {
+        return 1.0;
+    }
+    let found = sentence_stems
+        .iter()
+        .filter(|s| text_stems.contains(*s))
+        .count();
+    found as f64 / sentence_stems.len() as f64
+}
+
+/// Asks the model whether `text` supports the given sentences. Sentences it skips are treated as
+/// supported.
+async fn judge(
+    text: &str,
+    sentences: &[&str],
+) -> anyhow::Result<(Vec<bool>, crate::openai::Usage)> {
+    let config = crate::config::config();
+    let mut content = format!("Text:\n{text}\n\nSentences:\n");
+    for (i, sentence) in sentences.iter().enumerate() {
+        content.push_str(&format!("{}. {sentence}\n", i + 1));
+    }
+
+    let (response, usage): (GroundingResponse, _) =
+        crate::openai::complete(&config.grounding_model, SYSTEM_PROMPT, &content, schema()).await?;
+
+    let mut supported = vec![true; sentences.len()];
+    for verdict in response.verdicts {
+        if let Some(s) = usize::try_from(verdict.sentence - 1)
+            .ok()
+            .and_then(|i| supported.get_mut(i))
+        {
+            *s = verdict.supported;
+        }
+    }
+    Ok((supported, usage))
+}
+
+/// Verdicts of the overlap check, false for the sentences left to the model. A summary translated
+/// from the text's language is left to the model entirely, and cannot be checked at all in
+/// lexical mode, which gives None.
+fn lexical_verdicts(
+    sentences: &[&str],
+    text: &str,
+    translated: bool,
+    mode: Mode,
+    min_overlap: f64,
+) -> Option<Vec<bool>> {
+    match (translated, mode) {
+        (_, Mode::Off) | (true, Mode::Lexical) => None,
+        (true, Mode::Model) => Some(vec![false; sentences.len()]),
+        (false, Mode::Lexical | Mode::Model) => {
+            let text_stems = stems(text);
+            Some(
+                sentences
+                    .iter()
+                    .map(|s| overlap(s, &text_stems) >= min_overlap)
+                    .collect(),
+            )
+        }
+    }
+}
+
+/// Checks the story's summary against its text, drops or marks unsupported sentences and sets
+/// the groundedness score. The model judges against `condensed`, the text the summary was made
+/// from, since the full text may not fit its context. Does nothing when grounding is off, the
+/// story has no summary, or the summary is translated and only lexical grounding is configured.
+pub(crate) async fn verify(
+    story: &mut crate::Story,
+    profile: &crate::profile::Profile,
+    condensed: &str,
+) -> anyhow::Result<crate::openai::Usage> {
+    let config = crate::config::config();
+    let mut usage = crate::openai::Usage::default();
+    let Some(summary) = &story.summary else {
+        return Ok(usage);
+    };
+
+    let sentences: Vec<&str> = std::iter::once(summary.tldr.as_str())
+        .chain(summary.key_points.iter().map(String::as_str))
+        .collect();
+    let translated = story
+        .source_language
+        .as_deref()
+        .is_some_and(|language| language != profile.language);
+    let Some(mut supported) = lexical_verdicts(
+        &sentences,
+        story.text.as_deref().unwrap_or_default(),
+        translated,
+        config.grounding_mode,
+        config.grounding_min_overlap,
+    ) else {
+        if translated && config.grounding_mode == Mode::Lexical {
+            tracing::info!(
+                id = story.id,
+                "Summary is translated, skipping lexical grounding"
+            );
+        }
+        return Ok(usage);
+    };
+
+    if config.grounding_mode == Mode::Model {
+        let doubtful: Vec<usize> = (0..sentences.len()).filter(|i| !supported[*i]).collect();
+        if !doubtful.is_empty() {
+            let doubtful_sentences: Vec<&str> = doubtful.iter().map(|i| sentences[*i]).collect();
+            let (verdicts, judge_usage) = judge(condensed, &doubtful_sentences).await?;
+            usage += &judge_usage;
+            for (i, verdict) in doubtful.into_iter().zip(verdicts) {
+                supported[i] = verdict;
+            }
+        }
+    }
+
+    let groundedness =
+        supported.iter().filter(|s| **s).count() as f64 / supported.len().max(1) as f64;
+    tracing::info!(
+        id = story.id,
+        groundedness,
+        unsupported =? sentences
+            .iter()
+            .zip(&supported)
+            .filter(|(_, s)| !**s)
+            .map(|(sentence, _)| sentence)
+            .collect::<Vec<_>>(),
+        "Verified summary"
+    );
+
+    let summary = story.summary.as_mut().expect("Summary checked above");
+    if !supported[0] {
+        summary.tldr.insert_str(0, UNVERIFIED_MARKER);
+    }
+    let mut key_point_supported = supported[1..].iter();
+    match config.grounding_action {
+        Action::Drop => summary.key_points.retain(|_| {
+            *key_point_supported
+                .next()
+                .expect("One verdict per key point")
+        }),
+        Action::Mark => {
+            for (point, supported) in summary.key_points.iter_mut().zip(key_point_supported) {
+                if !supported {
+                    point.insert_str(0, UNVERIFIED_MARKER);
+                }
+            }
+        }
+    }
+    story.groundedness = Some(groundedness);
+
+    Ok(usage)
+}
+
+/// Whether the story's summary is too poorly supported to be sent.
+pub(crate) fn is_held_back(groundedness: Option<f64>) -> bool {
+    groundedness.is_some_and(|g| g < crate::config::config().min_groundedness)
+}
+
+/// Whether dropping unsupported key points left fewer than `SUMMARY_MIN_KEY_POINTS`.
+pub(crate) fn too_few_key_points(summary: Option<&crate::openai::Summary>) -> bool {
+    summary
+        .is_some_and(|s| s.key_points.len() < crate::config::config().summary_rules.min_key_points)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overlap() {
+        let text = stems(
+            "OpenAI released GPT-5 on Tuesday. The model scores 92% on the coding benchmark \
+            and is available through the API.",
+        );
+
+        assert_eq!(overlap("OpenAI releases GPT-5.", &text), 1.0);
+        assert_eq!(overlap("It scores 92% on coding.", &text), 1.0);
+        assert_eq!(
+            overlap("Google acquired the company for $3 billion.", &text),
+            0.0
+        );
+        assert_eq!(
+            overlap("The new model is available today.", &text),
+            2.0 / 3.0
+        );
+        assert_eq!(overlap("It is.", &text), 1.0);
+    }
+
+    #[test]
+    fn test_lexical_verdicts() {
+        let text = "OpenAI hat am Dienstag GPT-5 veröffentlicht. Das Modell erreicht 92 Prozent.";
+        let sentences = [
+            "The model was released on Tuesday with strong results.",
+            "Das Modell erreicht 92 Prozent.",
+        ];
+
+        assert_eq!(
+            lexical_verdicts(&sentences, text, false, Mode::Lexical, 0.5),
+            Some(vec![false, true])
+        );
+        // Translated summaries are only judged by the model.
+        assert_eq!(
+            lexical_verdicts(&sentences, text, true, Mode::Model, 0.5),
+            Some(vec![false, false])
+        );
+        assert_eq!(
+            lexical_verdicts(&sentences, text, true, Mode::Lexical, 0.5),
+            None
+        );
+        assert_eq!(
+            lexical_verdicts(&sentences, text, false, Mode::Off, 0.5),
+            None
+        );
+    }
+
+    #[test]
+    fn test_too_few_key_points() {
+        // The test config requires one key point.
+        let summary = crate::validation::tests::summary;
+        assert!(too_few_key_points(Some(&summary("A tl;dr.", &[]))));
+        assert!(!too_few_key_points(Some(&summary(
+            "A tl;dr.",
+            &["A point."]
+        ))));
+        assert!(!too_few_key_points(None));
+    }
+
+    #[test]
+    fn test_schema_is_strict() {
+        crate::openai::tests::assert_strict(&schema().schema);
+    }
+}
@@ -1,37 +1,516 @@
-pub(crate) async fn get_hackernews_top_stories() -> anyhow::Result<Vec<crate::Story>> {
-    let response = crate::CLIENT
-        .get("https://hacker-news.firebaseio.com/v0/topstories.json")
-        .send()
-        .await?;
-    let stories: Vec<i64> = response.json::<Vec<i64>>().await?
-        [..crate::config::config().num_titles_to_request]
-        .to_vec();
-
-    let mut enriched_stories = Vec::with_capacity(stories.len());
-
-    let mut queries_set: tokio::task::JoinSet<anyhow::Result<crate::Story>> =
+/// Item exactly as returned by `/v0/item/<id>.json`. Which fields are present depends on the
+/// item type and whether it has been deleted or killed.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[allow(unused)]
+struct RawItem {
+    id: i64,
+    #[serde(default)]
+    deleted: bool,
+    #[serde(default)]
+    dead: bool,
+    #[serde(rename = "type")]
+    item_type: Option<String>,
+    by: Option<String>,
+    time: Option<i64>,
+    text: Option<String>,
+    parent: Option<i64>,
+    title: Option<String>,
+    url: Option<String>,
+    score: Option<i64>,
+    descendants: Option<i64>,
+    #[serde(default)]
+    parts: Vec<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Item {
+    Story {
+        id: i64,
+        title: String,
+        url: Option<String>,
+        score: i64,
+        descendants: Option<i64>,
+        /// Unix time of the submission.
+        time: Option<i64>,
+    },
+    Job {
+        id: i64,
+        title: String,
+        url: Option<String>,
+        score: i64,
+        time: Option<i64>,
+    },
+    Poll {
+        id: i64,
+        title: String,
+        score: i64,
+        descendants: Option<i64>,
+        time: Option<i64>,
+        parts: Vec<i64>,
+    },
+    Comment {
+        id: i64,
+        parent: i64,
+    },
+    PollOpt {
+        id: i64,
+    },
+    Deleted {
+        id: i64,
+    },
+    Dead {
+        id: i64,
+    },
+}
+
+impl TryFrom<RawItem> for Item {
+    type Error = anyhow::Error;
+
+    fn try_from(raw: RawItem) -> anyhow::Result<Self> {
+        let id = raw.id;
+        if raw.deleted {
+            return Ok(Item::Deleted { id });
+        }
+        if raw.dead {
+            return Ok(Item::Dead { id });
+        }
+
+        let title = || {
+            raw.title
+                .clone()
+                .ok_or(anyhow::anyhow!("Item {id} has no title"))
+        };
+        // Jobs and text posts sometimes carry an empty url instead of omitting it.
+        let url = raw.url.clone().filter(|url| !url.is_empty());
+
+        Ok(match raw.item_type.as_deref() {
+            Some("story") => Item::Story {
+                id,
+                title: title()?,
+                url,
+                score: raw.score.unwrap_or_default(),
+                descendants: raw.descendants,
+                time: raw.time,
+            },
+            Some("job") => Item::Job {
+                id,
+                title: title()?,
+                url,
+                score: raw.score.unwrap_or_default(),
+                time: raw.time,
+            },
+            Some("poll") => Item::Poll {
+                id,
+                title: title()?,
+                score: raw.score.unwrap_or_default(),
+                descendants: raw.descendants,
+                time: raw.time,
+                parts: raw.parts,
+            },
+            Some("comment") => Item::Comment {
+                id,
+                parent: raw
+                    .parent
+                    .ok_or(anyhow::anyhow!("Comment {id} has no parent"))?,
+            },
+            Some("pollopt") => Item::PollOpt { id },
+            other => anyhow::bail!("Item {id} has unknown type {other:?}"),
+        })
+    }
+}
+
+impl Item {
+    /// Converts items that can appear on the front page into stories. Comments, poll options
+    /// and deleted or dead items have nothing to summarize and yield `None`.
+    pub(crate) fn into_story(self) -> Option<crate::Story> {
+        match self {
+            Item::Story {
+                id,
+                title,
+                url,
+                score,
+                descendants,
+                time,
+            } => Some(crate::Story {
+                id,
+                score,
+                descendants,
+                time,
+                title,
+                url,
+                story_type: "story".to_string(),
+                ..Default::default()
+            }),
+            Item::Job {
+                id,
+                title,
+                url,
+                score,
+                time,
+            } => Some(crate::Story {
+                id,
+                score,
+                time,
+                title,
+                url,
+                story_type: "job".to_string(),
+                ..Default::default()
+            }),
+            Item::Poll {
+                id,
+                title,
+                score,
+                descendants,
+                time,
+                ..
+            } => Some(crate::Story {
+                id,
+                score,
+                descendants,
+                time,
+                title,
+                story_type: "poll".to_string(),
+                ..Default::default()
+            }),
+            Item::Comment { .. }
+            | Item::PollOpt { .. }
+            | Item::Deleted { .. }
+            | Item::Dead { .. } => None,
+        }
+    }
+}
+
+fn api_url() -> &'static str {
+    &crate::config::config().hn_api_url
+}
+
+/// GETs a url with retries on network and HTTP errors.
+async fn get_with_retry(url: &str) -> anyhow::Result<String> {
+    backoff::future::retry_notify(
+        crate::backoff::backoff_default(),
+        || async {
+            crate::cassette::send(crate::CLIENT.get(url))
+                .await
+                .and_then(crate::cassette::Response::error_for_status)
+                .map(|response| response.body)
+                .map_err(crate::cassette::retryable)
+        },
+        |e: anyhow::Error, duration: std::time::Duration| {
+            tracing::warn!(
+                error =? e,
+                error_at =? duration.as_secs(),
+                url = url,
+                "Error when querying Hacker News, retrying"
+            );
+        },
+    )
+    .await
+}
+
+fn parse_item(json: &str) -> anyhow::Result<Item> {
+    // Items that do not exist are returned as `null`.
+    let raw: Option<RawItem> = serde_json::from_str(json)?;
+    raw.ok_or(anyhow::anyhow!("Item not found"))?.try_into()
+}
+
+pub(crate) async fn get_item(id: i64) -> anyhow::Result<Item> {
+    parse_item(&get_item_json(id).await?)
+}
+
+async fn get_item_json(id: i64) -> anyhow::Result<String> {
+    get_with_retry(&format!("{}/item/{id}.json", api_url())).await
+}
+
+async fn get_story_ids(source: crate::profile::Source) -> anyhow::Result<Vec<i64>> {
+    let mut ids: Vec<i64> = serde_json::from_str(
+        &get_with_retry(&format!("{}/{}.json", api_url(), source.endpoint())).await?,
+    )?;
+
+    let num_titles_to_request = crate::config::config().num_titles_to_request;
+    if ids.len() < num_titles_to_request {
+        tracing::warn!(
+            source =% source,
+            num_returned = ids.len(),
+            num_requested = num_titles_to_request,
+            "Hacker News returned fewer stories than requested"
+        );
+    }
+    ids.truncate(num_titles_to_request);
+
+    Ok(ids)
+}
+
+/// Merges the story lists so every story is only fetched once, remembering which lists it
+/// appeared on. Stories keep the order in which they first appear.
+fn merge_story_lists(
+    lists: Vec<(crate::profile::Source, Vec<i64>)>,
+) -> Vec<(i64, Vec<crate::profile::Source>)> {
+    let mut merged: Vec<(i64, Vec<crate::profile::Source>)> = Vec::new();
+    for (source, ids) in lists {
+        for id in ids {
+            match merged.iter_mut().find(|(merged_id, _)| *merged_id == id) {
+                Some((_, sources)) => sources.push(source),
+                None => merged.push((id, vec![source])),
+            }
+        }
+    }
+    merged
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Updates {
+    items: Vec<i64>,
+}
+
+/// Refreshes the local item cache. Only listed stories that have not been seen before or that
+/// are listed in `/v0/updates.json` are downloaded. Each refreshed story gets a score snapshot
+/// so its trajectory can be followed over time.
+pub(crate) async fn poll_updates(
+    db: &rusqlite::Connection,
+    sources: &[crate::profile::Source],
+) -> anyhow::Result<()> {
+    const MAX_ITEM_KEY: &str = "max_item";
+
+    let max_item: i64 =
+        serde_json::from_str(&get_with_retry(&format!("{}/maxitem.json", api_url())).await?)?;
+    let updates: Updates =
+        serde_json::from_str(&get_with_retry(&format!("{}/updates.json", api_url())).await?)?;
+
+    let mut lists = Vec::with_capacity(sources.len());
+    for &source in sources {
+        lists.push((source, get_story_ids(source).await?));
+    }
+    let story_ids: Vec<i64> = merge_story_lists(lists.clone())
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect();
+
+    let updated: std::collections::HashSet<i64> = updates.items.into_iter().collect();
+    let mut to_fetch = Vec::new();
+    let mut num_new = 0;
+    for &id in &story_ids {
+        if crate::db::get_cached_item(db, id)?.is_none() {
+            num_new += 1;
+            to_fetch.push(id);
+        } else if updated.contains(&id) {
+            to_fetch.push(id);
+        }
+    }
+
+    let mut queries_set: tokio::task::JoinSet<(i64, anyhow::Result<String>)> =
+        tokio::task::JoinSet::new();
+    for id in to_fetch {
+        queries_set.spawn(async move { (id, get_item_json(id).await) });
+    }
+
+    let polled_at = crate::run::unix_now();
+    let mut num_fetched = 0;
+    while let Some(res) = queries_set.join_next().await {
+        match res.expect("JoinSet to work") {
+            (id, Ok(json)) => {
+                crate::db::upsert_item(db, id, &json, polled_at)?;
+                if let Some(story) = parse_item(&json).ok().and_then(Item::into_story) {
+                    crate::db::insert_item_snapshot(db, &story, polled_at)?;
+                }
+                num_fetched += 1;
+            }
+            (id, Err(e)) => tracing::error!(error =? e, id = id, "Error refreshing item"),
+        }
+    }
+
+    for (source, ids) in &lists {
+        crate::db::set_story_list(db, *source, ids)?;
+    }
+    let last_max_item = crate::db::get_hn_state(db, MAX_ITEM_KEY)?;
+    crate::db::set_hn_state(db, MAX_ITEM_KEY, max_item)?;
+
+    tracing::info!(
+        max_item = max_item,
+        num_items_since_last_poll = last_max_item.map(|last| max_item - last),
+        num_new = num_new,
+        num_fetched = num_fetched,
+        num_unchanged = story_ids.len() - num_fetched,
+        "Polled Hacker News for updates"
+    );
+
+    Ok(())
+}
+
+/// Builds the stories of the given lists from the item cache filled by [`poll_updates`].
+pub(crate) fn get_cached_stories(
+    db: &rusqlite::Connection,
+    sources: &[crate::profile::Source],
+    run: &mut crate::run::Run,
+) -> anyhow::Result<Vec<crate::Story>> {
+    let mut lists = Vec::with_capacity(sources.len());
+    for &source in sources {
+        lists.push((source, crate::db::get_story_list(db, source)?));
+    }
+
+    let mut stories = Vec::new();
+    for (id, sources) in merge_story_lists(lists) {
+        let item = crate::db::get_cached_item(db, id)?
+            .ok_or(anyhow::anyhow!("Item not in cache"))
+            .and_then(|json| parse_item(&json));
+        match item {
+            Ok(item) => stories.extend(item.into_story().map(|mut story| {
+                story.sources = sources;
+                story
+            })),
+            Err(e) => run.record_error(id, crate::run::Stage::Fetch, &e),
+        }
+    }
+    Ok(stories)
+}
+
+pub(crate) async fn get_hackernews_stories(
+    sources: &[crate::profile::Source],
+    run: &mut crate::run::Run,
+) -> anyhow::Result<Vec<crate::Story>> {
+    let deadline = crate::config::deadline(crate::config::config().fetch_deadline_secs);
+
+    let lists = tokio::time::timeout_at(deadline, async {
+        let mut lists = Vec::with_capacity(sources.len());
+        for &source in sources {
+            lists.push((source, get_story_ids(source).await?));
+        }
+        anyhow::Ok(lists)
+    })
+    .await
+    .map_err(|_| anyhow::anyhow!("Fetch deadline exceeded when getting story lists"))??;
+    let stories = merge_story_lists(lists);
+
+    let mut queries_set: tokio::task::JoinSet<(i64, anyhow::Result<Option<crate::Story>>)> =
         tokio::task::JoinSet::new();
 
-    for story in stories {
+    let pending = stories.iter().map(|(id, _)| *id).collect();
+    for (id, sources) in stories {
         queries_set.spawn(async move {
-            let response = crate::CLIENT
-                .get(format!(
-                    "https://hacker-news.firebaseio.com/v0/item/{}.json",
+            let result = get_item(id).await.map(|item| {
+                let story = item.clone().into_story();
+                if story.is_none() {
+                    tracing::info!(item =? item, "Skipping item that is not a story");
+                }
+                story.map(|mut story| {
+                    story.sources = sources;
                     story
-                ))
-                .send()
-                .await?;
-
-            Ok(response.json::<crate::Story>().await?)
+                })
+            });
+            (id, result)
         });
     }
 
-    while let Some(results) = queries_set.join_next().await {
-        match results? {
-            Ok(story) => enriched_stories.push(story),
-            Err(e) => tracing::error!(error =? e, "Error getting story"),
-        }
+    Ok(run
+        .join_until(
+            &mut queries_set,
+            pending,
+            deadline,
+            crate::run::Stage::Fetch,
+        )
+        .await
+        .into_iter()
+        .flatten()
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_story() {
+        let item = parse_item(include_str!("../tests/fixtures/hn/story.json")).unwrap();
+        assert_eq!(
+            item,
+            Item::Story {
+                id: 8863,
+                title: "My YC app: Dropbox - Throw away your USB drive".to_string(),
+                url: Some("http://www.getdropbox.com/u/2/screencast.html".to_string()),
+                score: 111,
+                descendants: Some(71),
+                time: Some(1175714200),
+            }
+        );
+        assert_eq!(item.into_story().unwrap().story_type, "story");
     }
 
-    Ok(enriched_stories)
+    #[test]
+    fn test_parse_ask_hn_without_url() {
+        let item = parse_item(include_str!("../tests/fixtures/hn/ask.json")).unwrap();
+        let story = item.into_story().unwrap();
+        assert_eq!(story.id, 121003);
+        assert_eq!(story.url, None);
+    }
+
+    #[test]
+    fn test_parse_job_with_empty_url() {
+        let item = parse_item(include_str!("../tests/fixtures/hn/job.json")).unwrap();
+        assert!(matches!(
+            item,
+            Item::Job {
+                id: 192327,
+                url: None,
+                ..
+            }
+        ));
+        assert_eq!(item.into_story().unwrap().story_type, "job");
+    }
+
+    #[test]
+    fn test_parse_poll() {
+        let item = parse_item(include_str!("../tests/fixtures/hn/poll.json")).unwrap();
+        let Item::Poll { parts, .. } = &item else {
+            panic!("Expected poll, got {item:?}");
+        };
+        assert_eq!(parts, &[126810, 126811, 126812]);
+        assert_eq!(item.into_story().unwrap().story_type, "poll");
+    }
+
+    #[test]
+    fn test_parse_comment() {
+        let item = parse_item(include_str!("../tests/fixtures/hn/comment.json")).unwrap();
+        assert_eq!(
+            item,
+            Item::Comment {
+                id: 2921983,
+                parent: 2921506
+            }
+        );
+        assert!(item.into_story().is_none());
+    }
+
+    #[test]
+    fn test_parse_deleted_and_dead() {
+        let item = parse_item(include_str!("../tests/fixtures/hn/deleted.json")).unwrap();
+        assert_eq!(item, Item::Deleted { id: 42930412 });
+        assert!(item.into_story().is_none());
+
+        let item = parse_item(include_str!("../tests/fixtures/hn/dead.json")).unwrap();
+        assert_eq!(item, Item::Dead { id: 42931057 });
+        assert!(item.into_story().is_none());
+    }
+
+    #[test]
+    fn test_merge_story_lists() {
+        use crate::profile::Source;
+
+        let merged = merge_story_lists(vec![
+            (Source::Top, vec![3, 1, 2]),
+            (Source::Show, vec![4, 1]),
+        ]);
+        assert_eq!(
+            merged,
+            vec![
+                (3, vec![Source::Top]),
+                (1, vec![Source::Top, Source::Show]),
+                (2, vec![Source::Top]),
+                (4, vec![Source::Show]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_missing_item() {
+        assert!(parse_item("null").is_err());
+    }
 }
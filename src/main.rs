@@ -1,13 +1,32 @@
 use tracing_subscriber::util::SubscriberInitExt;
 
+pub(crate) mod alert;
 mod backoff;
+pub(crate) mod cache;
+pub(crate) mod cassette;
 pub(crate) mod config;
+pub(crate) mod consensus;
 pub(crate) mod db;
+pub(crate) mod dedup;
+pub(crate) mod eval;
+pub(crate) mod fallback;
+pub(crate) mod filters;
 pub(crate) mod google_chat;
+pub(crate) mod grounding;
 pub(crate) mod hn_api;
 mod lints;
 pub(crate) mod openai;
+pub(crate) mod profile;
+pub(crate) mod prompt;
+pub(crate) mod ranking;
+pub(crate) mod run;
 pub(crate) mod scraper;
+pub(crate) mod style;
+pub(crate) mod tokens;
+pub(crate) mod topics;
+pub(crate) mod trends;
+pub(crate) mod triage;
+pub(crate) mod validation;
 pub(crate) static CLIENT: std::sync::LazyLock<reqwest::Client> =
     std::sync::LazyLock::new(reqwest::Client::new);
 
@@ -25,6 +44,92 @@ struct Args {
     #[arg(short, long, default_value = "false")]
     #[arg(help = "Log to console")]
     log_to_console: bool,
+
+    #[arg(short, long, default_value = "false")]
+    #[arg(
+        help = "Keep running, polling Hacker News for updates and sending digests on an interval"
+    )]
+    daemon: bool,
+
+    #[arg(short, long)]
+    #[arg(help = "Only run the named profile. Can be repeated. Defaults to all profiles")]
+    profile: Vec<String>,
+
+    #[arg(long, default_value = "false", conflicts_with = "daemon")]
+    #[arg(
+        help = "Fetch and scrape stories and estimate the tokens and cost of summarizing them without calling the model"
+    )]
+    dry_run: bool,
+
+    #[arg(long, default_value = "false")]
+    #[arg(help = "Always call the model instead of reusing cached responses")]
+    no_cache: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Clone, clap::Subcommand)]
+enum Command {
+    /// Show entity mentions per week and newly emerging entities
+    Trends {
+        #[arg(long, default_value = "8")]
+        #[arg(help = "Number of weeks to look back")]
+        weeks: i64,
+
+        #[arg(long, default_value = "10")]
+        #[arg(help = "Number of entities to show per week")]
+        top: usize,
+
+        #[arg(long, default_value = "2")]
+        #[arg(help = "Entities first mentioned within this many weeks count as emerging")]
+        emerging_weeks: i64,
+
+        #[arg(long, default_value = "2")]
+        #[arg(help = "Minimum number of stories for an entity to count as emerging")]
+        min_mentions: i64,
+
+        #[arg(long, default_value = "false")]
+        #[arg(help = "Print the trends as json")]
+        json: bool,
+    },
+    /// Show the number of cached model responses, cache hits and tokens saved
+    CacheStats {
+        #[arg(long, default_value = "false")]
+        #[arg(help = "Print the stats as json")]
+        json: bool,
+    },
+    /// Classify a labeled dataset of stories and report how well each model and prompt does
+    Eval {
+        #[arg(help = "JSON file with a list of stories, each with title, text and expected label")]
+        dataset: String,
+
+        #[arg(long)]
+        #[arg(help = "Topic the labels belong to. Defaults to the first topic of the profile")]
+        topic: Option<String>,
+
+        #[arg(long)]
+        #[arg(help = "Model to evaluate. Can be repeated. Defaults to the profile's model")]
+        model: Vec<String>,
+
+        #[arg(long)]
+        #[arg(
+            help = "File with a system prompt to evaluate. Can be repeated. Defaults to the profile's prompt"
+        )]
+        prompt: Vec<String>,
+
+        #[arg(long)]
+        #[arg(help = "Report of an earlier evaluation to compare against")]
+        baseline: Option<String>,
+
+        #[arg(long)]
+        #[arg(help = "Write the report to this file, to use as a later baseline")]
+        save: Option<String>,
+
+        #[arg(long, default_value = "false")]
+        #[arg(help = "Print the report as json")]
+        json: bool,
+    },
 }
 
 #[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
@@ -32,6 +137,8 @@ struct Story {
     id: i64,
     score: i64,
     descendants: Option<i64>,
+    /// Unix time of the submission.
+    time: Option<i64>,
 
     title: String,
 
@@ -40,13 +147,40 @@ struct Story {
     #[serde(rename = "type")]
     story_type: String,
 
+    /// Hacker News lists the story was found on.
+    #[serde(default)]
+    sources: Vec<profile::Source>,
+    /// Other submissions of the same story, merged into this one before summarizing.
+    #[serde(default)]
+    duplicates: Vec<dedup::Duplicate>,
+
     // Not included in json response. Our own enrichment.
-    ai_impact_score: Option<crate::openai::Category>,
+    /// Filter rule that always includes the story, if any.
+    filter_rule: Option<String>,
+    /// 0-100 as judged by triage from the title and domain only.
+    triage_score: Option<i64>,
+    classifications: Option<Vec<crate::topics::Classification>>,
+    /// 0-100 as judged by the model.
+    relevance_score: Option<i64>,
+    rationale: Option<String>,
+    /// 0.0-1.0 as judged by the model.
+    confidence: Option<f64>,
+    /// Share of the votes agreeing with the labels. Only set when more than one vote was cast.
+    consensus: Option<f64>,
+    /// Share of the summary's sentences supported by the text. Only set when grounding is on.
+    groundedness: Option<f64>,
+    /// ISO 639-3 code of the detected language of the text.
+    source_language: Option<String>,
     text: Option<String>,
-    summary: Option<Vec<String>>,
+    summary: Option<crate::openai::Summary>,
+    /// Parts of the score the story was ranked by in the digest.
+    ranking: Option<crate::ranking::Components>,
 
     // Statistics
     usage: Option<crate::openai::Usage>,
+    /// `usage` split by the model it was spent on, so it can be priced per model.
+    #[serde(default)]
+    usage_by_model: std::collections::BTreeMap<String, crate::openai::Usage>,
 }
 
 impl Default for Story {
@@ -55,13 +189,26 @@ impl Default for Story {
             id: 0,
             score: 0,
             descendants: None,
+            time: None,
             title: "".to_string(),
             url: None,
             story_type: "".to_string(),
-            ai_impact_score: None,
+            sources: Vec::new(),
+            duplicates: Vec::new(),
+            filter_rule: None,
+            triage_score: None,
+            classifications: None,
+            relevance_score: None,
+            rationale: None,
+            confidence: None,
+            consensus: None,
+            groundedness: None,
+            source_language: None,
             text: None,
             summary: None,
+            ranking: None,
             usage: None,
+            usage_by_model: Default::default(),
         }
     }
 }
@@ -77,84 +224,93 @@ fn remove_stories_without_url(stories: Vec<Story>) -> Vec<Story> {
     stories.into_iter().filter(|s| s.url.is_some()).collect()
 }
 
-async fn summarize_and_score_scraped_stories(stories: Vec<Story>) -> anyhow::Result<Vec<Story>> {
-    let mut join_set: tokio::task::JoinSet<anyhow::Result<Story>> = tokio::task::JoinSet::new();
-    let mut enriched_stories = Vec::with_capacity(stories.len());
+async fn summarize_and_score_scraped_stories(
+    stories: Vec<Story>,
+    profile: &'static profile::Profile,
+    run: &mut run::Run,
+) -> anyhow::Result<Vec<Story>> {
+    let deadline = config::deadline(config::config().llm_deadline_secs);
+    let mut join_set: tokio::task::JoinSet<(i64, anyhow::Result<Story>)> =
+        tokio::task::JoinSet::new();
 
+    let pending = stories.iter().map(|s| s.id).collect();
     for story in stories {
         let url = story.url.clone().unwrap();
+        let id = story.id;
         join_set.spawn(async move {
-            let story = crate::openai::enrich_story(story).await?;
+            let story = match crate::openai::enrich_story(story, profile).await {
+                Ok(story) => story,
+                Err(e) => return (id, Err(e)),
+            };
             tracing::info!(
                 title = story.title,
                 url = url,
-                ai_score =? story.ai_impact_score,
+                profile = profile.name,
+                classifications =? story.classifications,
+                relevance_score = story.relevance_score,
+                confidence = story.confidence,
+                rationale = story.rationale,
                 votes = story.score,
                 usage =? story.usage,
                 "Scored and summarized story"
             );
-            Ok(story)
+            (id, Ok(story))
         });
     }
 
-    while let Some(result) = join_set.join_next().await {
-        match result.expect("JoinSet to work") {
-            Ok(story) => enriched_stories.push(story),
-            Err(e) => tracing::error!(error =? e, "Error enriching story"),
-        }
-    }
+    let enriched_stories = run
+        .join_until(&mut join_set, pending, deadline, run::Stage::Summarize)
+        .await;
 
-    let total_usage = crate::openai::Usage {
-        prompt_tokens: enriched_stories
-            .iter()
-            .map(|s| s.usage.as_ref().expect("Usage").prompt_tokens)
-            .sum(),
-        completion_tokens: enriched_stories
-            .iter()
-            .map(|s| s.usage.as_ref().expect("Usage").completion_tokens)
-            .sum(),
-        total_tokens: enriched_stories
-            .iter()
-            .map(|s| s.usage.as_ref().expect("Usage").total_tokens)
-            .sum(),
-    };
+    let mut total_usage = crate::openai::Usage::default();
+    for story in &enriched_stories {
+        total_usage += story.usage.as_ref().expect("Usage");
+    }
 
     tracing::info!(
         num_stories = enriched_stories.len(),
+        profile = profile.name,
         total_usage =? total_usage,
         "Finished enriching stories"
     );
+    run.usage += &total_usage;
 
     Ok(enriched_stories)
 }
 
-fn sort_stories(stories: &mut [Story]) {
-    stories.sort_by(|a, b| {
-        let a_score = a.ai_impact_score.as_ref().unwrap();
-        let b_score = b.ai_impact_score.as_ref().unwrap();
-
-        if a_score == b_score {
-            b.score.cmp(&a.score)
-        } else {
-            a_score.cmp(b_score)
-        }
-    })
-}
-
-async fn get_summary(args: Args) -> anyhow::Result<()> {
-    let db = db::open_db(args.reset)?;
-
-    tracing::info!("Database opened");
-    let processed_stories: Vec<i64> = db::get_processed_stories(&db)?;
+async fn get_summary(
+    args: Args,
+    db: &rusqlite::Connection,
+    profiles: &[&'static profile::Profile],
+    run: &mut run::Run,
+) -> anyhow::Result<()> {
+    let started_at = run::unix_now();
+    let mut processed_stories = std::collections::HashMap::with_capacity(profiles.len());
+    for profile in profiles {
+        processed_stories.insert(
+            profile.name.as_str(),
+            db::get_processed_stories(db, &profile.name)?,
+        );
+    }
 
     tracing::info!(
-        num_processed_stories = processed_stories.len(),
+        num_processed_stories =? processed_stories
+            .iter()
+            .map(|(profile, ids)| (profile, ids.len()))
+            .collect::<Vec<_>>(),
         "Got already processed stories"
     );
 
-    let stories = hn_api::get_hackernews_top_stories().await?;
+    let sources = profile::sources(profiles);
+
+    let stories = if args.daemon {
+        hn_api::get_cached_stories(db, &sources, run)?
+    } else {
+        hn_api::get_hackernews_stories(&sources, run).await?
+    };
+    run.stories_fetched = stories.len();
 
-    tracing::info!(num_stories = stories.len(), "Got top stories");
+    tracing::info!(num_stories = stories.len(), "Got stories");
 
     let num_stories = stories.len();
     let stories = remove_job_adverts(stories);
@@ -170,56 +326,270 @@ async fn get_summary(args: Args) -> anyhow::Result<()> {
         "Removed stories without url"
     );
 
+    let num_stories = stories.len();
+    let stories = filters::apply(
+        stories,
+        &config::config().filter_rules,
+        run::unix_now(),
+        run,
+    );
+    tracing::info!(
+        num_stories_filtered_out = num_stories - stories.len(),
+        "Applied filter rules"
+    );
+
+    // Fetching and scraping is shared, so a story is kept as long as one profile still wants it.
+    let wanted_by = |story: &Story, profile: &profile::Profile| {
+        story.sources.iter().any(|s| profile.sources.contains(s))
+            && !processed_stories[profile.name.as_str()].contains(&story.id)
+    };
+
     let num_stories = stories.len();
     let stories: Vec<_> = stories
         .into_iter()
-        .filter(|s| !processed_stories.contains(&s.id))
+        .filter(|s| profiles.iter().any(|p| wanted_by(s, p)))
         .collect();
 
     tracing::info!(
         num_stories_filtered_out = num_stories - stories.len(),
         "Filtered out already processed stories"
     );
+    run.stories_filtered = stories.len();
+
+    // Dry runs do not call the triage model, its cost is estimated and every story is kept.
+    let (stories, triage_usage) =
+        if args.dry_run && config::config().triage_mode == triage::Mode::Model {
+            let usage = triage::estimate_usage(&stories, profiles)?;
+            (stories, usage)
+        } else {
+            let stories = triage::triage(stories, profiles, run).await;
+            (stories, openai::Usage::default())
+        };
+    run.stories_triaged = stories.len();
 
-    let stories = scraper::enrich_stories(stories, args.export_text).await?;
+    let stories = scraper::enrich_stories(stories, args.export_text, run).await?;
+    run.stories_scraped = stories.len();
 
     tracing::info!(
         num_scraped_stories = stories.len(),
         "Finished scraping stories"
     );
 
-    let mut stories = summarize_and_score_scraped_stories(stories).await?;
+    let num_stories = stories.len();
+    let stories = dedup::deduplicate(
+        stories,
+        config::config().dedup_mode,
+        config::config().dedup_max_distance,
+    );
+    tracing::info!(
+        num_duplicates_merged = num_stories - stories.len(),
+        "Merged near-duplicate stories"
+    );
+
+    if args.dry_run {
+        print_estimate(profiles, &stories, wanted_by, &triage_usage);
+        return Ok(());
+    }
+
+    let mut failed_profiles = Vec::new();
+    for profile in profiles {
+        let stories = stories
+            .iter()
+            .filter(|s| wanted_by(s, profile))
+            .cloned()
+            .collect();
+        match digest_profile(&args, db, profile, stories, run).await {
+            // Only a delivered digest counts as a run of the profile, so in daemon mode a failed
+            // one is tried again on the next poll rather than after a full interval.
+            Ok(()) => db::set_profile_last_run_at(db, &profile.name, started_at)?,
+            Err(e) => {
+                tracing::error!(error =? e, profile = profile.name, "Error when building digest");
+                failed_profiles.push(format!("{}: {e:#}", profile.name));
+            }
+        }
+    }
+
+    if !failed_profiles.is_empty() {
+        anyhow::bail!("Profiles failed: {}", failed_profiles.join("; "));
+    }
+
+    Ok(())
+}
+
+/// Prints the tokens and cost summarizing the stories would take for every profile, and the
+/// shared cost of triaging them.
+fn print_estimate(
+    profiles: &[&'static profile::Profile],
+    stories: &[Story],
+    wanted_by: impl Fn(&Story, &profile::Profile) -> bool,
+    triage_usage: &openai::Usage,
+) {
+    println!(
+        "{:<16} {:>8} {:>14} {:>18} {:>10}",
+        "Profile", "Stories", "Prompt tokens", "Completion tokens", "Cost ($)"
+    );
+
+    let mut total = openai::Usage::default();
+    let mut total_cost = 0.0;
+    for profile in profiles {
+        let mut usage = openai::Usage::default();
+        let mut usage_by_model = std::collections::BTreeMap::new();
+        let mut num_stories = 0;
+        for story in stories.iter().filter(|s| wanted_by(s, profile)) {
+            for (model, model_usage) in openai::estimate_usage(story, profile) {
+                usage += &model_usage;
+                *usage_by_model.entry(model).or_default() += &model_usage;
+            }
+            num_stories += 1;
+        }
+        let cost = tokens::total_cost(&usage_by_model);
+        println!(
+            "{:<16} {:>8} {:>14} {:>18} {:>10.4}",
+            profile.name, num_stories, usage.prompt_tokens, usage.completion_tokens, cost
+        );
+        total_cost += cost;
+        total += &usage;
+    }
 
-    sort_stories(&mut stories);
+    if config::config().triage_mode == triage::Mode::Model {
+        println!(
+            "{:<16} {:>8} {:>14} {:>18} {:>10.4}",
+            "Triage",
+            stories.len(),
+            triage_usage.prompt_tokens,
+            triage_usage.completion_tokens,
+            tokens::cost(&config::config().triage_model, triage_usage)
+        );
+        total_cost += tokens::cost(&config::config().triage_model, triage_usage);
+        total += triage_usage;
+    }
 
-    let mut stories = stories[..config::config()
-        .max_number_of_stories_to_present
-        .min(stories.len())]
-        .to_vec();
+    println!(
+        "{:<16} {:>8} {:>14} {:>18} {:>10.4}",
+        "Total", "", total.prompt_tokens, total.completion_tokens, total_cost
+    );
+}
+
+/// Summarizes, ranks and delivers the scraped stories for a single profile.
+async fn digest_profile(
+    args: &Args,
+    db: &rusqlite::Connection,
+    profile: &'static profile::Profile,
+    stories: Vec<Story>,
+    run: &mut run::Run,
+) -> anyhow::Result<()> {
+    let mut stories = summarize_and_score_scraped_stories(stories, profile, run).await?;
+    run.stories_scored += stories.len();
+    db::insert_story_scores(db, run.id, profile, &stories)?;
+    trends::tag_stories(db, &stories)?;
+
+    if config::config().consensus_action == consensus::Action::Exclude {
+        stories.retain(|s| {
+            let low = consensus::is_low(s.consensus);
+            if low {
+                tracing::info!(
+                    title = s.title,
+                    consensus = s.consensus,
+                    "Excluded story with low consensus"
+                );
+            }
+            !low
+        });
+    }
 
     stories.retain(|s| {
-        s.ai_impact_score.as_ref().unwrap() == &crate::openai::Category::High && s.summary.is_some()
+        let held_back = grounding::is_held_back(s.groundedness);
+        if held_back {
+            tracing::info!(
+                title = s.title,
+                groundedness = s.groundedness,
+                "Held back story with ungrounded summary"
+            );
+        }
+        !held_back
     });
 
+    ranking::rank(&mut stories, &profile.ranking, run::unix_now());
+    tracing::info!(
+        profile = profile.name,
+        ranking =? stories
+            .iter()
+            .map(|s| (s.id, s.ranking))
+            .collect::<Vec<_>>(),
+        "Ranked stories"
+    );
+
+    let ranked = stories;
+    let mut stories: Vec<Story> = ranked
+        .iter()
+        .filter(|s| {
+            s.classifications
+                .as_deref()
+                .is_some_and(topics::is_included)
+                && s.summary.is_some()
+        })
+        .take(profile.max_number_of_stories_to_present)
+        .cloned()
+        .collect();
+
+    let mut fallback = None;
     if stories.is_empty() {
-        tracing::info!("No stories to send to google chat");
+        let policy = config::config().fallback_policy;
+        let max = config::config()
+            .fallback_max_stories
+            .min(profile.max_number_of_stories_to_present);
+        stories = match policy {
+            fallback::Policy::Skip => Vec::new(),
+            fallback::Policy::Promote => fallback::promote(&profile.topics, &ranked, max),
+            fallback::Policy::Quiet => fallback::quiet(&ranked, max),
+        };
+        tracing::info!(
+            profile = profile.name,
+            policy =% policy,
+            num_stories = stories.len(),
+            "No stories met the threshold, applied fallback policy"
+        );
+        run.fallbacks.push(fallback::Fallback {
+            profile: profile.name.clone(),
+            policy,
+            num_stories: stories.len(),
+        });
+        fallback = Some(policy);
+    }
+
+    if stories.is_empty() {
+        tracing::info!(profile = profile.name, "No stories to send");
         return Ok(());
     }
 
     if args.export_text {
         let json_summaries = serde_json::to_string_pretty(&stories)?;
+        let path = format!("export/exported_stories_{}.json", profile.name);
         std::fs::create_dir_all("export")?;
-        std::fs::write("export/exported_stories.json", json_summaries)?;
-        tracing::info!("Exported stories to export/exported_stories.json");
+        std::fs::write(&path, json_summaries)?;
+        tracing::info!(path = path, "Exported stories");
     }
 
-    let message = google_chat::create_message(stories.clone())?;
-    google_chat::send_message(message, &config::config().google_chat_webhook_url).await?;
-    tracing::info!("Sent message to google chat");
+    for sink in &profile.sinks {
+        tokio::time::timeout(
+            std::time::Duration::from_secs(config::config().delivery_deadline_secs),
+            sink.deliver(profile, &stories, run.stories_skipped, fallback),
+        )
+        .await
+        .map_err(|_| anyhow::anyhow!("Delivery deadline exceeded when sending message"))??;
+    }
+    if fallback == Some(fallback::Policy::Quiet) {
+        // The listed stories were not summarized in the note, so they stay unprocessed.
+        tracing::info!(profile = profile.name, "Sent quiet day note");
+        return Ok(());
+    }
+    tracing::info!(profile = profile.name, "Sent digest");
+    run.stories_sent += stories.len();
 
-    db::insert_stories(&db, &stories)?;
+    db::insert_stories(db, &profile.name, &stories)?;
     tracing::info!(
         num = stories.len(),
+        profile = profile.name,
         ids =? stories.iter().map(|s: &Story| s.id).collect::<Vec<_>>(),
         "Inserted stories into db"
     );
@@ -227,8 +597,28 @@ async fn get_summary(args: Args) -> anyhow::Result<()> {
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
+async fn run_eval(
+    profile: &profile::Profile,
+    dataset: &str,
+    topic: Option<&str>,
+    models: &[String],
+    prompts: &[String],
+    baseline: Option<&str>,
+    save: Option<&str>,
+    json: bool,
+) -> anyhow::Result<()> {
+    let examples = eval::load_dataset(dataset)?;
+    let baseline = baseline.map(eval::load_report).transpose()?;
+    let report = eval::eval(&examples, profile, topic, models, prompts).await?;
+    if let Some(path) = save {
+        std::fs::write(path, serde_json::to_string_pretty(&report)?)?;
+    }
+    eval::print_report(&report, baseline.as_ref(), json)
+}
+
 #[tokio::main]
-async fn main() {
+async fn main() -> std::process::ExitCode {
     use tracing_subscriber::layer::Layer;
     use tracing_subscriber::layer::SubscriberExt;
 
@@ -266,15 +656,184 @@ async fn main() {
         "Starting AI Summarizer"
     );
 
-    // Timeout after an hour
-    const TIMEOUT: u64 = 60 * 60 * 60;
-    tokio::select! {
-        res = get_summary(args) => match res {
-            Ok(_) => tracing::info!("AI Summarizer finished"),
-            Err(e) => tracing::error!(error =? e, "Error when getting summary"),
+    let db = match db::open_db(args.reset) {
+        Ok(db) => db,
+        Err(e) => {
+            tracing::error!(error =? e, "Error opening database");
+            return std::process::ExitCode::FAILURE;
+        }
+    };
+    tracing::info!("Database opened");
+
+    if !args.no_cache
+        && let Err(e) = cache::enable()
+    {
+        tracing::error!(error =? e, "Error opening response cache");
+        return std::process::ExitCode::FAILURE;
+    }
+
+    let profiles: Vec<&'static profile::Profile> = config::config()
+        .profiles
+        .iter()
+        .filter(|p| args.profile.is_empty() || args.profile.contains(&p.name))
+        .collect();
+    if let Some(unknown) = args
+        .profile
+        .iter()
+        .find(|name| !profiles.iter().any(|p| &&p.name == name))
+    {
+        tracing::error!(profile = unknown, "Unknown profile");
+        return std::process::ExitCode::FAILURE;
+    }
+
+    if let Some(command) = &args.command {
+        let result = match *command {
+            Command::Trends {
+                weeks,
+                top,
+                emerging_weeks,
+                min_mentions,
+                json,
+            } => {
+                let query = trends::Query {
+                    weeks,
+                    top,
+                    emerging_weeks,
+                    min_mentions,
+                };
+                trends::query(&db, &query, run::unix_now())
+                    .and_then(|trends| trends::print_trends(&trends, json))
+            }
+            Command::CacheStats { json } => {
+                db::get_cache_stats(&db).and_then(|stats| cache::print_stats(&stats, json))
+            }
+            Command::Eval {
+                ref dataset,
+                ref topic,
+                ref model,
+                ref prompt,
+                ref baseline,
+                ref save,
+                json,
+            } => {
+                run_eval(
+                    profiles[0],
+                    dataset,
+                    topic.as_deref(),
+                    model,
+                    prompt,
+                    baseline.as_deref(),
+                    save.as_deref(),
+                    json,
+                )
+                .await
+            }
+        };
+        return match result {
+            Ok(()) => std::process::ExitCode::SUCCESS,
+            Err(e) => {
+                tracing::error!(error =? e, command =? command, "Error running command");
+                std::process::ExitCode::FAILURE
+            }
+        };
+    }
+
+    if args.daemon {
+        daemon(args, &db, &profiles).await
+    } else {
+        match run_once(args, &db, &profiles).await {
+            run::Outcome::Success => std::process::ExitCode::SUCCESS,
+            run::Outcome::Failure | run::Outcome::Timeout => std::process::ExitCode::FAILURE,
+        }
+    }
+}
+
+/// Runs the pipeline once and records the outcome in the run history.
+async fn run_once(
+    args: Args,
+    db: &rusqlite::Connection,
+    profiles: &[&'static profile::Profile],
+) -> run::Outcome {
+    let mut run = match run::Run::start(db, profiles) {
+        Ok(run) => run,
+        Err(e) => {
+            tracing::error!(error =? e, "Error recording run start");
+            return run::Outcome::Failure;
+        }
+    };
+
+    let args_dry_run = args.dry_run;
+
+    // Backstop timeout after an hour. The per-stage deadlines should normally fire well before.
+    const TIMEOUT: u64 = 60 * 60;
+    let (outcome, error) = tokio::select! {
+        res = get_summary(args, db, profiles, &mut run) => match res {
+            Ok(_) => {
+                tracing::info!("AI Summarizer finished");
+                (run::Outcome::Success, None)
+            },
+            Err(e) => {
+                tracing::error!(error =? e, "Error when getting summary");
+                (run::Outcome::Failure, Some(format!("{e:#}")))
+            },
         },
         _ = tokio::time::sleep(std::time::Duration::from_secs(TIMEOUT)) => {
             tracing::error!(timeout = TIMEOUT, "Timeout when getting summary");
+            (run::Outcome::Timeout, Some(format!("Timed out after {TIMEOUT} seconds")))
+        }
+    };
+
+    if !args_dry_run && let Err(e) = alert::notify(db, &run, outcome, error.as_deref()).await {
+        tracing::error!(error =? e, "Error sending alert to ops channel");
+    }
+
+    if let Err(e) = run.finish(db, outcome, error.as_deref()) {
+        tracing::error!(error =? e, "Error recording run outcome");
+        return run::Outcome::Failure;
+    }
+
+    outcome
+}
+
+/// Polls Hacker News for updates every `POLL_INTERVAL_SECS` and runs the pipeline on the
+/// cached items for every profile whose digest interval has passed since its last run.
+async fn daemon(
+    args: Args,
+    db: &rusqlite::Connection,
+    profiles: &[&'static profile::Profile],
+) -> std::process::ExitCode {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(
+        config::config().poll_interval_secs,
+    ));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    let sources = profile::sources(profiles);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = hn_api::poll_updates(db, &sources).await {
+            tracing::error!(error =? e, "Error polling Hacker News for updates");
+        }
+
+        let mut due_profiles = Vec::new();
+        for &profile in profiles {
+            match db::get_profile_last_run_at(db, &profile.name) {
+                Ok(last_run_at) => {
+                    if last_run_at.is_none_or(|last_run_at| {
+                        run::unix_now() - last_run_at >= profile.digest_interval_secs
+                    }) {
+                        due_profiles.push(profile);
+                    }
+                }
+                Err(e) => {
+                    tracing::error!(error =? e, profile = profile.name, "Error getting last run");
+                }
+            }
+        }
+
+        if !due_profiles.is_empty() {
+            run_once(args.clone(), db, &due_profiles).await;
         }
     }
 }
@@ -283,56 +842,36 @@ async fn main() {
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_sort_stories() {
-        let mut stories = vec![
-            Story {
-                id: 0,
-                score: 200,
-                ai_impact_score: Some(crate::openai::Category::High),
-                ..Default::default()
-            },
-            Story {
-                id: 1,
-                score: 100,
-                ai_impact_score: Some(crate::openai::Category::Medium),
-                ..Default::default()
-            },
-            Story {
-                id: 2,
-                score: 0,
-                ai_impact_score: Some(crate::openai::Category::Low),
-                ..Default::default()
-            },
-            Story {
-                id: 3,
-                score: 400,
-                ai_impact_score: Some(crate::openai::Category::High),
-                ..Default::default()
-            },
-            Story {
-                id: 4,
-                score: 300,
-                ai_impact_score: Some(crate::openai::Category::Medium),
-                ..Default::default()
-            },
-            Story {
-                id: 5,
-                score: 300,
-                ai_impact_score: Some(crate::openai::Category::High),
-                ..Default::default()
-            },
-        ];
-
-        sort_stories(&mut stories);
-
-        // Stories should be sorted by impact score (High > Medium > Low > Zero)
-        // Within the same impact score, higher HN score should come first
-        assert_eq!(stories[0].id, 3); // High impact
-        assert_eq!(stories[1].id, 5); // High impact
-        assert_eq!(stories[2].id, 0); // High impact
-        assert_eq!(stories[3].id, 4); // Medium impact
-        assert_eq!(stories[4].id, 1); // Medium impact
-        assert_eq!(stories[5].id, 2); // Low impact
+    /// Replays the recorded `daily` cassette through the whole pipeline. A miss means the
+    /// scraped text, a prompt, a schema or the rendered message changed.
+    #[tokio::test]
+    async fn test_replay_daily_cassette() {
+        assert_eq!(config::config().cassette_mode, cassette::Mode::Replay);
+
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        db::init_db(&db, false).unwrap();
+        let profiles: Vec<&'static profile::Profile> = config::config().profiles.iter().collect();
+        let mut run = run::Run::start(&db, &profiles).unwrap();
+
+        get_summary(
+            <Args as clap::Parser>::parse_from(["ai-summarizer"]),
+            &db,
+            &profiles,
+            &mut run,
+        )
+        .await
+        .unwrap();
+
+        assert!(run.errors.is_empty(), "{:?}", run.errors);
+        assert_eq!(run.stories_fetched, 3);
+        assert_eq!(run.stories_scraped, 2);
+        assert_eq!(run.stories_sent, 2);
+        assert_eq!(run.usage.total_tokens, 7200);
+        assert_eq!(db::get_processed_stories(&db, "default").unwrap().len(), 2);
+        assert!(
+            db::get_profile_last_run_at(&db, "default")
+                .unwrap()
+                .is_some()
+        );
     }
 }
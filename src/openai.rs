@@ -1,33 +1,3 @@
-#[derive(
-    Debug,
-    Clone,
-    PartialEq,
-    Eq,
-    PartialOrd,
-    Ord,
-    serde::Serialize,
-    serde::Deserialize,
-    schemars::JsonSchema,
-)]
-#[serde(deny_unknown_fields)]
-pub(crate) enum Category {
-    High,
-    Medium,
-    Low,
-    Zero,
-}
-
-impl std::fmt::Display for crate::openai::Category {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            crate::openai::Category::High => write!(f, "High"),
-            crate::openai::Category::Medium => write!(f, "Medium"),
-            crate::openai::Category::Low => write!(f, "Low"),
-            crate::openai::Category::Zero => write!(f, "Zero"),
-        }
-    }
-}
-
 #[derive(Debug, serde::Serialize)]
 pub(crate) struct OpenAIChatCompletionQuery {
     model: String,
@@ -118,54 +88,536 @@ pub(crate) struct Choice {
     pub(crate) index: i64,
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
 pub(crate) struct Usage {
     pub(crate) prompt_tokens: i64,
     pub(crate) completion_tokens: i64,
     pub(crate) total_tokens: i64,
+    /// Tokens of responses reused from the cache. They are not part of the billed tokens above.
+    #[serde(default)]
+    pub(crate) cached_tokens: i64,
+}
+
+impl std::ops::AddAssign<&Usage> for Usage {
+    fn add_assign(&mut self, other: &Usage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+        self.cached_tokens += other.cached_tokens;
+    }
+}
+
+/// Summarizes, scores and verifies the story. Summaries failing verification are made again up
+/// to `GROUNDING_RETRIES` times, the last attempt is kept either way unless dropping unsupported
+/// key points left too few of them, then the story fails. The text is condensed once and reused
+/// by every attempt.
+pub(crate) async fn enrich_story(
+    mut story: crate::Story,
+    profile: &crate::profile::Profile,
+) -> anyhow::Result<crate::Story> {
+    let config = crate::config::config();
+    story.source_language = story
+        .text
+        .as_deref()
+        .and_then(crate::style::detect_language)
+        .map(|language| language.code().to_string());
+    let (text, mut usage) = condense_text(&story, profile).await?;
+    let mut usage_by_model = std::collections::BTreeMap::new();
+    usage_by_model.insert(profile.model.clone(), usage.clone());
+
+    for attempt in 0.. {
+        let mut enriched =
+            summarize_and_score_story(story.clone(), profile, &text, attempt).await?;
+        usage += enriched.usage.as_ref().unwrap_or(&Usage::default());
+        for (model, vote_usage) in &enriched.usage_by_model {
+            *usage_by_model.entry(model.clone()).or_default() += vote_usage;
+        }
+        let grounding_usage = crate::grounding::verify(&mut enriched, profile, &text).await?;
+        usage += &grounding_usage;
+        *usage_by_model
+            .entry(config.grounding_model.clone())
+            .or_default() += &grounding_usage;
+
+        let too_few_key_points = crate::grounding::too_few_key_points(enriched.summary.as_ref());
+        if attempt == config.grounding_retries && too_few_key_points {
+            anyhow::bail!(
+                "Only {} key points are grounded in the text, at least {} are required",
+                enriched.summary.as_ref().map_or(0, |s| s.key_points.len()),
+                config.summary_rules.min_key_points
+            );
+        }
+        if attempt == config.grounding_retries
+            || !(too_few_key_points || crate::grounding::is_held_back(enriched.groundedness))
+        {
+            enriched.usage = Some(usage);
+            enriched.usage_by_model = usage_by_model;
+            return Ok(enriched);
+        }
+        tracing::info!(
+            id = story.id,
+            groundedness = enriched.groundedness,
+            too_few_key_points,
+            attempt,
+            "Summary not grounded in the text, retrying"
+        );
+    }
+    unreachable!("The loop only ends by returning")
 }
 
-pub(crate) async fn enrich_story(mut story: crate::Story) -> anyhow::Result<crate::Story> {
-    let (summary, usage) = summarize_and_score_text_categorical(&story.title).await?;
-    story.summary = Some(summary.summary);
-    story.ai_impact_score = Some(summary.ai_impact);
+/// Summarizes and scores the story from its condensed `text`. With consensus scoring every voter
+/// scores the same text, the labels are combined and the summary and rationale are taken from a
+/// vote that agrees with the combined labels. Each attempt uses new samples so retries are not
+/// answered from the cache.
+async fn summarize_and_score_story(
+    mut story: crate::Story,
+    profile: &crate::profile::Profile,
+    text: &str,
+    attempt: usize,
+) -> anyhow::Result<crate::Story> {
+    let config = crate::config::config();
+    let mut usage = Usage::default();
+    let (system_prompt, content) = prompts(&story, profile, text);
+    let voters = crate::consensus::voters(profile);
+
+    let mut votes = Vec::with_capacity(voters.len());
+    let mut last_error = None;
+    for (model, sample) in &voters {
+        let result = complete_summary(
+            model,
+            &system_prompt,
+            profile,
+            &content,
+            sample + attempt * config.consensus_samples.max(1),
+        )
+        .await
+        .and_then(|(response, vote_usage)| {
+            usage += &vote_usage;
+            *story.usage_by_model.entry(model.to_string()).or_default() += &vote_usage;
+            let classifications = crate::topics::classify(&profile.topics, &response.topics)?;
+            Ok((response, classifications))
+        });
+        match result {
+            Ok(vote) => votes.push(vote),
+            // A failed voter only counts as missing as long as another one answers.
+            Err(e) if voters.len() > 1 => {
+                tracing::warn!(error =? e, id = story.id, model, sample, "Vote failed");
+                last_error = Some(e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    if votes.is_empty() {
+        return Err(last_error.unwrap_or(anyhow::anyhow!("No voters")));
+    }
+
+    let classifications: Vec<_> = votes.iter().map(|(_, c)| c.clone()).collect();
+    // The schema only describes the ranges, so out of range answers are clamped.
+    let scores: Vec<_> = votes
+        .iter()
+        .map(|(r, _)| r.relevance_score.clamp(0, 100))
+        .collect();
+    let (classifications, consensus) = crate::consensus::combine(
+        &profile.topics,
+        &classifications,
+        &scores,
+        config.consensus_method,
+    )?;
+    let num_votes = votes.len();
+    let relevance_score = (scores.iter().sum::<i64>() as f64 / num_votes as f64).round() as i64;
+    let confidence = votes
+        .iter()
+        .map(|(r, _)| r.confidence.clamp(0.0, 1.0))
+        .sum::<f64>()
+        / num_votes as f64;
+    let index = votes
+        .iter()
+        .position(|(_, c)| *c == classifications)
+        .unwrap_or(0);
+    let (response, _) = votes.swap_remove(index);
+
+    story.summary = Some(response.summary);
+    story.classifications = Some(classifications);
+    story.relevance_score = Some(relevance_score);
+    story.rationale = Some(response.rationale);
+    story.confidence = Some(confidence);
+    story.consensus = (voters.len() > 1).then_some(consensus);
 
     story.usage = Some(usage);
     Ok(story)
 }
 
-async fn summarize_and_score_text_categorical(
+/// Name of this backend in the response cache.
+const BACKEND: &str = "openai";
+
+/// Limit on how often chunk summaries are summarized again, in case they do not get shorter.
+const MAX_REDUCE_ROUNDS: usize = 3;
+
+/// Assumed completion tokens of a summary and of a chunk summary when estimating costs.
+const ESTIMATED_SUMMARY_TOKENS: i64 = 800;
+const ESTIMATED_CHUNK_SUMMARY_TOKENS: i64 = 300;
+
+/// Title, url and text of the story as sent to the model.
+fn story_content(story: &crate::Story, text: &str) -> String {
+    format!(
+        "Title: {}\nUrl: {}\n\n{}",
+        story.title,
+        story.url.as_deref().unwrap_or_default(),
+        text
+    )
+}
+
+/// System and user prompt of the profile rendered for the story, with `text` as the text. The
+/// profile's output style is added to the system prompt.
+fn prompts(
+    story: &crate::Story,
+    profile: &crate::profile::Profile,
     text: &str,
-) -> anyhow::Result<(SummaryResponse, crate::openai::Usage)> {
-    let query = crate::openai::OpenAIChatCompletionQuery::new(
-        crate::config::config().model.clone(),
-        crate::openai::OpenAIChatCompletionQuery::system_prompt_and_content_to_messages(
-            &crate::config::config().system_prompt,
-            text,
-        ),
-        schema_for_summarizer_response(),
-    );
+) -> (String, String) {
+    let variables = crate::prompt::Variables::new(story, &profile.audience, text);
+    (
+        crate::prompt::render(&system_prompt_template(story, profile), &variables),
+        crate::prompt::render(&profile.user_prompt, &variables),
+    )
+}
+
+/// The profile's system prompt template with its output style for the story, as it is rendered.
+pub(crate) fn system_prompt_template(
+    story: &crate::Story,
+    profile: &crate::profile::Profile,
+) -> String {
+    let source_language = story
+        .source_language
+        .as_deref()
+        .and_then(whatlang::Lang::from_code);
+    format!(
+        "{}\n\n{}",
+        profile.system_prompt,
+        crate::style::instructions(profile, source_language)
+    )
+}
 
-    let response = crate::CLIENT
-        .post("https://api.openai.com/v1/chat/completions")
-        .header(reqwest::header::USER_AGENT, "test")
-        .bearer_auth(&crate::config::config().api_key)
-        .json(&query)
-        .send()
+/// Asks for a summary and sends it back with the problems found while it fails validation, at
+/// most `SUMMARY_REPAIR_ATTEMPTS` times. Only a summary passing validation is cached, a repaired
+/// one under the original request so the next run does not need the repair again.
+async fn complete_summary(
+    model: &str,
+    system_prompt: &str,
+    profile: &crate::profile::Profile,
+    content: &str,
+    sample: usize,
+) -> anyhow::Result<(SummaryResponse, Usage)> {
+    let config = crate::config::config();
+    let schema = schema_for_summarizer_response(profile);
+    let cache_key = cache_key(model, system_prompt, content, &schema, sample)?;
+    let mut cached = cached::<SummaryResponse>(&cache_key).await;
+    let mut usage = Usage::default();
+    let mut request = content.to_string();
+
+    for repair in 0.. {
+        // Cached summaries are validated too, the rules may have changed since.
+        let (response, sent) = match cached.take() {
+            Some((response, cached_usage)) => {
+                usage += &cached_usage;
+                (response, None)
+            }
+            None => {
+                let (response, raw, response_usage) = send_completion::<SummaryResponse>(
+                    model,
+                    system_prompt,
+                    &request,
+                    schema.clone(),
+                )
+                .await?;
+                usage += &response_usage;
+                (response, Some((raw, response_usage)))
+            }
+        };
+
+        let errors = crate::validation::validate(
+            &response.summary,
+            &config.summary_rules,
+            crate::style::parse_language(&profile.language)?,
+        );
+        if errors.is_empty() {
+            if let Some((raw, response_usage)) = sent {
+                crate::cache::put(&cache_key, &raw, &response_usage).await;
+            }
+            return Ok((response, usage));
+        }
+        if repair == config.summary_repair_attempts {
+            anyhow::bail!(
+                "Summary still invalid after {repair} repairs: {}",
+                errors.join(" ")
+            );
+        }
+        tracing::info!(model, repair, errors =? errors, "Invalid summary, asking for a repair");
+
+        request = format!(
+            "{content}\n\nThis summary of the text was rejected:\n{}\n\nProblems:\n- {}\n\n\
+            Answer again with a summary that fixes these problems.",
+            serde_json::to_string(&response.summary)?,
+            errors.join("\n- ")
+        );
+    }
+    unreachable!("The loop only ends by returning")
+}
+
+/// Text the story is summarized and scored from in one call. Texts that fit `CHUNK_TOKENS` are
+/// used as they are. Longer texts are split into chunks that are summarized separately (map) and
+/// the final summary and scores are made from the chunk summaries (reduce).
+async fn condense_text(
+    story: &crate::Story,
+    profile: &crate::profile::Profile,
+) -> anyhow::Result<(String, crate::openai::Usage)> {
+    let model = &profile.model;
+    let mut usage = Usage::default();
+    let (mut text, budget) = crate::tokens::blocking({
+        let (story, profile) = (story.clone(), profile.clone());
+        move || {
+            (
+                input_text(&story, &profile.model).to_string(),
+                text_budget(&story, &profile),
+            )
+        }
+    })
+    .await?;
+
+    // Chunk summaries can themselves exceed the budget for very long texts, so they are reduced
+    // again until they fit.
+    for round in 0.. {
+        let chunks = crate::tokens::blocking({
+            let (model, text) = (model.clone(), text.clone());
+            move || {
+                (crate::tokens::count(&model, &text) > budget)
+                    .then(|| crate::tokens::chunk(&model, &text, budget))
+            }
+        })
         .await?;
+        let Some(chunks) = chunks else {
+            break;
+        };
+        if round == MAX_REDUCE_ROUNDS {
+            anyhow::bail!("Text still too long after {MAX_REDUCE_ROUNDS} rounds of summarizing");
+        }
+
+        tracing::info!(
+            id = story.id,
+            num_chunks = chunks.len(),
+            "Text too long, summarizing chunks"
+        );
+
+        let mut notes = String::from(
+            "The text was too long to read at once. These are the key points of its consecutive \
+            parts:\n",
+        );
+        for (i, chunk) in chunks.iter().enumerate() {
+            let (chunk_summary, chunk_usage): (ChunkSummary, _) = complete(
+                model,
+                &chunk_system_prompt(i + 1, chunks.len()),
+                &story_content(story, chunk),
+                schema_for_chunk_summary(),
+            )
+            .await?;
+            usage += &chunk_usage;
+
+            notes.push_str(&format!("\nPart {}:\n", i + 1));
+            for point in chunk_summary.key_points {
+                notes.push_str(&format!("- {point}\n"));
+            }
+        }
+        text = notes;
+    }
+
+    Ok((text, usage))
+}
+
+/// Story text cut to `MAX_INPUT_TOKENS` at a sentence boundary.
+fn input_text<'a>(story: &'a crate::Story, model: &str) -> &'a str {
+    let config = crate::config::config();
+    let text = story.text.as_deref().unwrap_or_default();
+    let truncated = crate::tokens::truncate(model, text, config.max_input_tokens);
+    if truncated.len() < text.len() {
+        tracing::info!(
+            id = story.id,
+            num_characters = text.len(),
+            num_characters_kept = truncated.len(),
+            "Truncated text to MAX_INPUT_TOKENS"
+        );
+    }
+    truncated
+}
+
+/// Tokens of the rendered prompts without the text.
+fn prompt_overhead(story: &crate::Story, profile: &crate::profile::Profile) -> usize {
+    let (system_prompt, user_prompt) = prompts(story, profile, "");
+    crate::tokens::count(&profile.model, &system_prompt)
+        + crate::tokens::count(&profile.model, &user_prompt)
+}
+
+/// Tokens left for the text in a single call once the prompts are accounted for.
+fn text_budget(story: &crate::Story, profile: &crate::profile::Profile) -> usize {
+    let overhead = prompt_overhead(story, profile);
+    crate::config::config()
+        .chunk_tokens
+        .saturating_sub(overhead)
+        .max(1)
+}
+
+/// Expected usage of `enrich_story` by model without calling the model. Prompt tokens are counted
+/// exactly up to the reduce step, completion tokens are assumed.
+pub(crate) fn estimate_usage(
+    story: &crate::Story,
+    profile: &crate::profile::Profile,
+) -> std::collections::BTreeMap<String, Usage> {
+    let model = &profile.model;
+    let text = input_text(story, model);
+    let budget = text_budget(story, profile);
+    let overhead = prompt_overhead(story, profile);
+
+    let mut usage = Usage::default();
+    let mut reduce_tokens = crate::tokens::count(model, text) as i64;
+    if reduce_tokens > budget as i64 {
+        let chunks = crate::tokens::chunk(model, text, budget);
+        for (i, chunk) in chunks.iter().enumerate() {
+            usage.prompt_tokens +=
+                (crate::tokens::count(model, &chunk_system_prompt(i + 1, chunks.len()))
+                    + crate::tokens::count(model, &story_content(story, chunk)))
+                    as i64;
+            usage.completion_tokens += ESTIMATED_CHUNK_SUMMARY_TOKENS;
+        }
+        reduce_tokens = chunks.len() as i64 * ESTIMATED_CHUNK_SUMMARY_TOKENS;
+    }
+    let mut usage_by_model = std::collections::BTreeMap::new();
+    usage_by_model.insert(model.clone(), usage);
+    for (voter, _) in crate::consensus::voters(profile) {
+        let usage: &mut Usage = usage_by_model.entry(voter.to_string()).or_default();
+        usage.prompt_tokens += overhead as i64 + reduce_tokens;
+        usage.completion_tokens += ESTIMATED_SUMMARY_TOKENS;
+    }
+    for usage in usage_by_model.values_mut() {
+        usage.total_tokens = usage.prompt_tokens + usage.completion_tokens;
+    }
+
+    usage_by_model
+}
+
+fn chunk_system_prompt(part: usize, num_parts: usize) -> String {
+    format!(
+        "You are reading part {part} of {num_parts} of a long text. List the key points of this \
+        part as short sentences in active voice, keeping the names of companies, models, people \
+        and products. Do not summarize the other parts."
+    )
+}
 
-    // If the request fails print the raw output for debugging.
+/// Sends a single chat completion and parses the answer following `schema` into `T`. Answers are
+/// taken from the response cache when the same request was made before.
+pub(crate) async fn complete<T: serde::de::DeserializeOwned>(
+    model: &str,
+    system_prompt: &str,
+    content: &str,
+    schema: Schema,
+) -> anyhow::Result<(T, Usage)> {
+    complete_sample(model, system_prompt, content, schema, 0).await
+}
+
+/// Like `complete`, for the `sample`th of several answers to the same request. Each sample is
+/// cached under its own key, otherwise every sample would get the first one's cached answer.
+pub(crate) async fn complete_sample<T: serde::de::DeserializeOwned>(
+    model: &str,
+    system_prompt: &str,
+    content: &str,
+    schema: Schema,
+    sample: usize,
+) -> anyhow::Result<(T, Usage)> {
+    let cache_key = cache_key(model, system_prompt, content, &schema, sample)?;
+    if let Some(cached) = cached(&cache_key).await {
+        return Ok(cached);
+    }
+
+    let (parsed, raw, usage) = send_completion(model, system_prompt, content, schema).await?;
+    crate::cache::put(&cache_key, &raw, &usage).await;
+    Ok((parsed, usage))
+}
+
+fn cache_key(
+    model: &str,
+    system_prompt: &str,
+    content: &str,
+    schema: &Schema,
+    sample: usize,
+) -> anyhow::Result<crate::cache::Key> {
+    let cache_input = match sample {
+        0 => content.to_string(),
+        _ => format!("{content}\n\nSample {sample}"),
+    };
+    Ok(crate::cache::Key::new(
+        BACKEND,
+        model,
+        system_prompt,
+        &serde_json::to_value(schema)?,
+        &cache_input,
+    ))
+}
+
+/// Cached answer for `key`, if there is one that still parses.
+async fn cached<T: serde::de::DeserializeOwned>(key: &crate::cache::Key) -> Option<(T, Usage)> {
+    let (response, usage) = crate::cache::get(key).await?;
+    match serde_json::from_str::<T>(&response) {
+        Ok(parsed) => {
+            tracing::debug!(model = key.model, "Using cached response");
+            Some((parsed, usage))
+        }
+        Err(e) => {
+            tracing::warn!(error =? e, "Ignoring unparsable cached response");
+            None
+        }
+    }
+}
+
+/// Sends the chat completion without looking at the cache. Returns the parsed answer together
+/// with the raw one for caching.
+async fn send_completion<T: serde::de::DeserializeOwned>(
+    model: &str,
+    system_prompt: &str,
+    content: &str,
+    schema: Schema,
+) -> anyhow::Result<(T, String, Usage)> {
+    tracing::debug!(
+        model = model,
+        prompt_tokens =
+            crate::tokens::count(model, system_prompt) + crate::tokens::count(model, content),
+        "Sending completion"
+    );
+
+    let query = OpenAIChatCompletionQuery::new(
+        model.to_string(),
+        OpenAIChatCompletionQuery::system_prompt_and_content_to_messages(system_prompt, content),
+        schema,
+    );
+
+    let config = crate::config::config();
+    let response = crate::cassette::send(
+        crate::CLIENT
+            .post(format!("{}/chat/completions", config.openai_base_url))
+            .header(reqwest::header::USER_AGENT, "test")
+            .bearer_auth(&config.api_key)
+            .json(&query),
+    )
+    .await?;
+
+    // If the request fails log the raw output for debugging.
     if let Err(e) = response.error_for_status_ref() {
-        println!("Error: {}", e);
-        println!("Raw output:\n{}", response.text().await?);
+        tracing::error!(error =% e, body = response.body, "Error querying model");
         return Err(anyhow::anyhow!("Error querying model: {}", e));
     }
 
-    let model_response: crate::openai::OpenAIChatCompletionResponse = response.json().await?;
-    let summary =
-        serde_json::from_str::<SummaryResponse>(&model_response.choices[0].message.content)
-            .unwrap();
-    Ok((summary, model_response.usage))
+    let model_response: OpenAIChatCompletionResponse = serde_json::from_str(&response.body)?;
+    let choice = model_response
+        .choices
+        .first()
+        .ok_or(anyhow::anyhow!("Model returned no choices"))?;
+    let parsed = serde_json::from_str::<T>(&choice.message.content)?;
+    Ok((parsed, choice.message.content.clone(), model_response.usage))
 }
 
 /// We enforce a json schema for the responses since we are working with structured data.
@@ -174,15 +626,96 @@ async fn summarize_and_score_text_categorical(
 pub(crate) struct SummaryResponse {
     #[schemars(required)]
     #[schemars(description = "Summary of the text")]
-    pub(crate) summary: Vec<String>,
+    pub(crate) summary: Summary,
+
+    /// Replaced by the schema generated from the configured topics.
+    #[schemars(required)]
+    pub(crate) topics: std::collections::BTreeMap<String, String>,
 
     #[schemars(required)]
-    #[schemars(description = "An array of tuples of the form (summary, ai_impact_score)")]
-    pub(crate) ai_impact: crate::openai::Category,
+    #[schemars(
+        description = "Relevance of the text to the topics from 0 (irrelevant) to 100 (must read)"
+    )]
+    pub(crate) relevance_score: i64,
+
+    #[schemars(required)]
+    #[schemars(description = "One sentence explaining the relevance labels and score")]
+    pub(crate) rationale: String,
+
+    #[schemars(required)]
+    #[schemars(description = "Confidence in the labels and score from 0.0 to 1.0")]
+    pub(crate) confidence: f64,
 }
 
-/// Creates the json schema for the output following the OpenAI completely non-standard format...
-fn schema_for_summarizer_response() -> crate::openai::Schema {
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Summary {
+    #[schemars(required)]
+    #[schemars(description = "A single sentence summing up the text")]
+    pub(crate) tldr: String,
+
+    #[schemars(required)]
+    #[schemars(description = "The most important points of the text, one short sentence each")]
+    pub(crate) key_points: Vec<String>,
+
+    #[schemars(required)]
+    #[schemars(description = "A single sentence on why this matters to the reader")]
+    pub(crate) why_it_matters: String,
+
+    #[schemars(required)]
+    #[schemars(description = "What kind of text this is")]
+    pub(crate) content_type: ContentType,
+
+    #[schemars(required)]
+    #[schemars(description = "Named entities mentioned in the text")]
+    pub(crate) entities: Entities,
+}
+
+#[derive(
+    Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, schemars::JsonSchema,
+)]
+pub(crate) enum ContentType {
+    Paper,
+    Release,
+    Opinion,
+    News,
+    Other,
+}
+
+impl std::fmt::Display for ContentType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContentType::Paper => write!(f, "Paper"),
+            ContentType::Release => write!(f, "Release"),
+            ContentType::Opinion => write!(f, "Opinion"),
+            ContentType::News => write!(f, "News"),
+            ContentType::Other => write!(f, "Other"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Entities {
+    #[schemars(required)]
+    #[schemars(description = "Companies, labs and other organisations")]
+    pub(crate) companies: Vec<String>,
+
+    #[schemars(required)]
+    #[schemars(description = "Machine learning models")]
+    pub(crate) models: Vec<String>,
+
+    #[schemars(required)]
+    #[schemars(description = "People")]
+    pub(crate) people: Vec<String>,
+
+    #[schemars(required)]
+    #[schemars(description = "Products, libraries and tools")]
+    pub(crate) products: Vec<String>,
+}
+
+/// Json schema for `T` in the format OpenAI accepts for strict structured outputs.
+pub(crate) fn schema_for<T: schemars::JsonSchema>() -> serde_json::Value {
     let schema = schemars::generate::SchemaSettings::default()
         .with_transform(schemars::transform::RecursiveTransform(
             |schema: &mut schemars::Schema| {
@@ -190,11 +723,114 @@ fn schema_for_summarizer_response() -> crate::openai::Schema {
             },
         ))
         .into_generator()
-        .into_root_schema_for::<SummaryResponse>();
+        .into_root_schema_for::<T>();
+
+    serde_json::to_value(schema).expect("Failed to convert schema to json")
+}
+
+/// Key points of one chunk of a text too long to summarize at once.
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ChunkSummary {
+    #[schemars(required)]
+    #[schemars(description = "The most important points of this part, one short sentence each")]
+    pub(crate) key_points: Vec<String>,
+}
+
+fn schema_for_chunk_summary() -> Schema {
+    Schema {
+        name: "chunk_summary".to_string(),
+        schema: schema_for::<ChunkSummary>(),
+        strict: true,
+    }
+}
+
+/// Creates the json schema for the output following the OpenAI completely non-standard format...
+fn schema_for_summarizer_response(profile: &crate::profile::Profile) -> crate::openai::Schema {
+    let mut schema = schema_for::<SummaryResponse>();
+    schema["properties"]["topics"] = crate::topics::schema(&profile.topics);
+    crate::style::describe_summary(&mut schema, profile);
 
     crate::openai::Schema {
-        name: "ai_relatedness_scores".to_string(),
-        schema: serde_json::to_value(schema).expect("Failed to convert schema to json"),
+        name: "topic_scores".to_string(),
+        schema,
         strict: true,
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// OpenAI strict mode requires every object to list all its properties as required and to
+    /// forbid additional properties.
+    pub(crate) fn assert_strict(schema: &serde_json::Value) {
+        match schema {
+            serde_json::Value::Object(object) => {
+                if let Some(serde_json::Value::Object(properties)) = object.get("properties") {
+                    let required: Vec<&str> = object["required"]
+                        .as_array()
+                        .expect("required to be set")
+                        .iter()
+                        .map(|r| r.as_str().unwrap())
+                        .collect();
+                    for property in properties.keys() {
+                        assert!(
+                            required.contains(&property.as_str()),
+                            "{property} not required"
+                        );
+                    }
+                    assert_eq!(object["additionalProperties"], false);
+                }
+                object.values().for_each(assert_strict);
+            }
+            serde_json::Value::Array(array) => array.iter().for_each(assert_strict),
+            _ => (),
+        }
+    }
+
+    #[test]
+    fn test_system_prompt_template() {
+        let story = crate::Story::default();
+        let profile = crate::profile::tests::profile();
+        let template = system_prompt_template(&story, &profile);
+        assert!(template.starts_with("Summarize\n\nWrite the summary in English."));
+
+        // Every style setting changes the template and with it the stored hash.
+        for styled in [
+            crate::profile::Profile {
+                language: "swe".to_string(),
+                ..profile.clone()
+            },
+            crate::profile::Profile {
+                reading_level: crate::style::ReadingLevel::General,
+                ..profile.clone()
+            },
+            crate::profile::Profile {
+                length: crate::style::Length::Short,
+                ..profile.clone()
+            },
+        ] {
+            assert_ne!(system_prompt_template(&story, &styled), template);
+        }
+    }
+
+    #[test]
+    fn test_schema_is_strict() {
+        let profile = crate::profile::Profile {
+            language: "swe".to_string(),
+            ..crate::profile::tests::profile()
+        };
+        let schema = schema_for_summarizer_response(&profile);
+        assert_strict(&schema.schema);
+        assert_eq!(
+            schema.schema["properties"]["summary"]["properties"]["tldr"]["description"],
+            "A single sentence summing up the text, in Swedish"
+        );
+        assert_strict(&schema_for_chunk_summary().schema);
+        assert_eq!(
+            schema.schema["properties"]["topics"]["properties"]["AI"]["enum"],
+            serde_json::json!(["High", "Medium", "Low", "Zero"])
+        );
+    }
+}
@@ -0,0 +1,285 @@
+//! Digest profiles. A single run fetches and scrapes stories once and then builds one digest per
+//! profile, each with its own sources, prompt, topics, ranking settings, sinks and schedule.
+
+/// Hacker News story lists a profile can draw from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Source {
+    Top,
+    Best,
+    New,
+    Ask,
+    Show,
+}
+
+impl Source {
+    pub(crate) fn endpoint(self) -> &'static str {
+        match self {
+            Source::Top => "topstories",
+            Source::Best => "beststories",
+            Source::New => "newstories",
+            Source::Ask => "askstories",
+            Source::Show => "showstories",
+        }
+    }
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Top => write!(f, "top"),
+            Source::Best => write!(f, "best"),
+            Source::New => write!(f, "new"),
+            Source::Ask => write!(f, "ask"),
+            Source::Show => write!(f, "show"),
+        }
+    }
+}
+
+/// Where a digest is delivered.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub(crate) enum Sink {
+    GoogleChat { webhook_url: String },
+}
+
+impl Sink {
+    /// Sends the digest. With a fallback policy the stories did not meet the threshold and are
+    /// presented as promoted stories or as a quiet day note.
+    pub(crate) async fn deliver(
+        &self,
+        profile: &Profile,
+        stories: &[crate::Story],
+        num_skipped: usize,
+        fallback: Option<crate::fallback::Policy>,
+    ) -> anyhow::Result<()> {
+        match self {
+            Sink::GoogleChat { webhook_url } => {
+                let message =
+                    crate::google_chat::create_message(profile, stories, num_skipped, fallback)?;
+                crate::google_chat::send_message(message, webhook_url).await
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub(crate) struct Profile {
+    pub(crate) name: String,
+    pub(crate) sources: Vec<Source>,
+    pub(crate) model: String,
+    /// Models that each score every story. Empty means only `model` scores.
+    pub(crate) consensus_models: Vec<String>,
+    /// Templates of the prompts the final summary is made with, see `prompt::render`.
+    pub(crate) system_prompt: String,
+    pub(crate) user_prompt: String,
+    /// Value of the `{audience}` prompt variable.
+    pub(crate) audience: String,
+    /// ISO 639-3 code of the language summaries are written in.
+    pub(crate) language: String,
+    pub(crate) reading_level: crate::style::ReadingLevel,
+    pub(crate) length: crate::style::Length,
+    pub(crate) topics: Vec<crate::topics::Topic>,
+    pub(crate) max_number_of_stories_to_present: usize,
+    /// Show the model's relevance score, rationale and confidence in the digest.
+    pub(crate) show_rationale: bool,
+    #[serde(skip)]
+    pub(crate) sinks: Vec<Sink>,
+    /// Daemon mode only. Minimum time between two digests of this profile.
+    pub(crate) digest_interval_secs: i64,
+    /// Weights of the score stories are ranked by within a label.
+    pub(crate) ranking: crate::ranking::Weights,
+}
+
+/// Profile as written in `PROFILES`. Missing settings are taken from the default profile.
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileConfig {
+    name: String,
+    sources: Option<Vec<Source>>,
+    model: Option<String>,
+    consensus_models: Option<Vec<String>>,
+    system_prompt: Option<String>,
+    /// Read from a file instead, takes precedence over `system_prompt`.
+    system_prompt_file: Option<String>,
+    user_prompt_file: Option<String>,
+    audience: Option<String>,
+    language: Option<String>,
+    reading_level: Option<crate::style::ReadingLevel>,
+    length: Option<crate::style::Length>,
+    topics: Option<Vec<crate::topics::Topic>>,
+    max_number_of_stories_to_present: Option<usize>,
+    show_rationale: Option<bool>,
+    sinks: Option<Vec<Sink>>,
+    digest_interval_secs: Option<i64>,
+    ranking: Option<crate::ranking::WeightsConfig>,
+}
+
+/// All sources used by the given profiles, without duplicates.
+pub(crate) fn sources(profiles: &[&Profile]) -> Vec<Source> {
+    let mut sources: Vec<Source> = Vec::new();
+    for source in profiles.iter().flat_map(|p| &p.sources) {
+        if !sources.contains(source) {
+            sources.push(*source);
+        }
+    }
+    sources
+}
+
+pub(crate) fn parse_profiles(json: &str, default: &Profile) -> anyhow::Result<Vec<Profile>> {
+    let configs: Vec<ProfileConfig> = serde_json::from_str(json)?;
+    if configs.is_empty() {
+        anyhow::bail!("At least one profile has to be configured");
+    }
+
+    let mut profiles: Vec<Profile> = Vec::with_capacity(configs.len());
+    for config in configs {
+        let ranking = match config.ranking {
+            Some(ranking) => ranking
+                .or(&default.ranking)
+                .map_err(|e| anyhow::anyhow!("Profile {}: {e}", config.name))?,
+            None => default.ranking.clone(),
+        };
+        let profile = Profile {
+            name: config.name,
+            sources: config.sources.unwrap_or_else(|| default.sources.clone()),
+            model: config.model.unwrap_or_else(|| default.model.clone()),
+            consensus_models: config
+                .consensus_models
+                .unwrap_or_else(|| default.consensus_models.clone()),
+            system_prompt: match (config.system_prompt_file, config.system_prompt) {
+                (Some(path), _) => crate::prompt::load(&path)?,
+                (None, Some(prompt)) => prompt,
+                (None, None) => default.system_prompt.clone(),
+            },
+            user_prompt: match config.user_prompt_file {
+                Some(path) => crate::prompt::load(&path)?,
+                None => default.user_prompt.clone(),
+            },
+            audience: config.audience.unwrap_or_else(|| default.audience.clone()),
+            language: config.language.unwrap_or_else(|| default.language.clone()),
+            reading_level: config.reading_level.unwrap_or(default.reading_level),
+            length: config.length.unwrap_or(default.length),
+            topics: config.topics.unwrap_or_else(|| default.topics.clone()),
+            max_number_of_stories_to_present: config
+                .max_number_of_stories_to_present
+                .unwrap_or(default.max_number_of_stories_to_present),
+            show_rationale: config.show_rationale.unwrap_or(default.show_rationale),
+            sinks: config.sinks.unwrap_or_else(|| default.sinks.clone()),
+            digest_interval_secs: config
+                .digest_interval_secs
+                .unwrap_or(default.digest_interval_secs),
+            ranking,
+        };
+
+        if profiles.iter().any(|p| p.name == profile.name) {
+            anyhow::bail!("Profile {} is configured more than once", profile.name);
+        }
+        if profile.sources.is_empty() {
+            anyhow::bail!("Profile {} has no sources", profile.name);
+        }
+        if profile.sinks.is_empty() {
+            anyhow::bail!("Profile {} has no sinks", profile.name);
+        }
+        crate::topics::validate_topics(&profile.topics)?;
+        crate::prompt::check_templates(&profile.system_prompt, &profile.user_prompt)
+            .map_err(|e| anyhow::anyhow!("Profile {}: {e}", profile.name))?;
+        crate::style::parse_language(&profile.language)
+            .map_err(|e| anyhow::anyhow!("Profile {}: {e}", profile.name))?;
+
+        profiles.push(profile);
+    }
+
+    Ok(profiles)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A profile with the default settings.
+    pub(crate) fn profile() -> Profile {
+        Profile {
+            name: "default".to_string(),
+            sources: vec![Source::Top],
+            model: "gpt-4o-mini".to_string(),
+            consensus_models: Vec::new(),
+            system_prompt: "Summarize".to_string(),
+            user_prompt: crate::prompt::DEFAULT_USER_PROMPT.to_string(),
+            audience: "a technical audience".to_string(),
+            language: "eng".to_string(),
+            reading_level: crate::style::ReadingLevel::Technical,
+            length: crate::style::Length::Standard,
+            topics: crate::topics::default_topics(),
+            max_number_of_stories_to_present: 5,
+            show_rationale: false,
+            sinks: vec![Sink::GoogleChat {
+                webhook_url: "https://chat.example.com/default".to_string(),
+            }],
+            digest_interval_secs: 86400,
+            ranking: crate::ranking::Weights {
+                relevance: 1.0,
+                points: 2.0,
+                comments: 1.0,
+                gravity: 0.2,
+                domains: Default::default(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_parse_profiles() {
+        let default = profile();
+
+        let profiles = parse_profiles(
+            r#"[
+                {
+                    "name": "ml",
+                    "model": "gpt-4o",
+                    "consensus_models": ["gpt-4o", "o3-mini"],
+                    "language": "swe",
+                    "length": "short"
+                },
+                {
+                    "name": "infra",
+                    "sources": ["top", "show"],
+                    "max_number_of_stories_to_present": 3,
+                    "ranking": {"gravity": 1.8},
+                    "sinks": [{"type": "google_chat", "webhook_url": "https://chat.example.com/infra"}]
+                }
+            ]"#,
+            &default,
+        )
+        .unwrap();
+
+        assert_eq!(profiles[0].name, "ml");
+        assert_eq!(profiles[0].sources, vec![Source::Top]);
+        assert_eq!(profiles[0].max_number_of_stories_to_present, 5);
+        assert_eq!(profiles[0].model, "gpt-4o");
+        assert_eq!(profiles[1].model, "gpt-4o-mini");
+        assert_eq!(profiles[0].consensus_models, ["gpt-4o", "o3-mini"]);
+        assert!(profiles[1].consensus_models.is_empty());
+        assert_eq!(profiles[0].length, crate::style::Length::Short);
+        assert_eq!(profiles[1].language, "eng");
+        assert_eq!(profiles[1].sources, vec![Source::Top, Source::Show]);
+        assert_eq!(profiles[1].max_number_of_stories_to_present, 3);
+        assert_eq!(profiles[0].ranking.gravity, 0.2);
+        assert_eq!(profiles[1].ranking.gravity, 1.8);
+        assert_eq!(profiles[1].ranking.points, 2.0);
+        assert!(matches!(
+            &profiles[1].sinks[..],
+            [Sink::GoogleChat { webhook_url }] if webhook_url == "https://chat.example.com/infra"
+        ));
+
+        assert!(parse_profiles(r#"[{"name": "a"}, {"name": "a"}]"#, &default).is_err());
+        assert!(parse_profiles(r#"[{"name": "a", "language": "xx"}]"#, &default).is_err());
+        assert!(
+            parse_profiles(
+                r#"[{"name": "a", "system_prompt": "For {reader}"}]"#,
+                &default
+            )
+            .is_err()
+        );
+        assert!(parse_profiles(r#"[{"name": "a", "sinks": []}]"#, &default).is_err());
+    }
+}
@@ -0,0 +1,157 @@
+//! Prompt templates. The system and user prompt of a profile are templates in which variables
+//! like `{title}` are replaced with the story's values before they are sent. Every template is
+//! identified by the hash of its content, which is stored with each summary so it is known which
+//! version of a prompt produced it.
+
+/// User prompt used when a profile has none, the title and url followed by the text.
+pub(crate) const DEFAULT_USER_PROMPT: &str = "Title: {title}\nUrl: {url}\n\n{text}";
+
+/// Names that can be used as `{name}` in a template.
+const VARIABLES: [&str; 7] = [
+    "title", "url", "domain", "hn_score", "text", "audience", "date",
+];
+
+static PLACEHOLDER: std::sync::LazyLock<regex::Regex> =
+    std::sync::LazyLock::new(|| regex::Regex::new(r"\{([a-z_]+)\}").expect("Valid regex"));
+
+/// Values of the template variables for one story.
+#[derive(Debug, Clone)]
+pub(crate) struct Variables<'a> {
+    pub(crate) title: &'a str,
+    pub(crate) url: &'a str,
+    pub(crate) domain: String,
+    pub(crate) hn_score: i64,
+    pub(crate) text: &'a str,
+    pub(crate) audience: &'a str,
+    /// Day of the run as `YYYY-MM-DD` in UTC.
+    pub(crate) date: String,
+}
+
+impl<'a> Variables<'a> {
+    pub(crate) fn new(story: &'a crate::Story, audience: &'a str, text: &'a str) -> Self {
+        let url = story.url.as_deref().unwrap_or_default();
+        Self {
+            title: &story.title,
+            url,
+            domain: crate::triage::domain(url).unwrap_or_default(),
+            hn_score: story.score,
+            text,
+            audience,
+            date: date(crate::run::unix_now()),
+        }
+    }
+}
+
+/// Checks that every `{name}` placeholder in the template is a known variable, so a typo does
+/// not end up in the prompt. Braces around anything else, like json examples, are left alone.
+pub(crate) fn check(template: &str) -> anyhow::Result<()> {
+    for captures in PLACEHOLDER.captures_iter(template) {
+        let name = &captures[1];
+        if !VARIABLES.contains(&name) {
+            anyhow::bail!(
+                "Unknown prompt variable {{{name}}}, expected one of {}",
+                VARIABLES.join(", ")
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Checks the system and user template of a profile. One of them has to include the text.
+pub(crate) fn check_templates(system_prompt: &str, user_prompt: &str) -> anyhow::Result<()> {
+    check(system_prompt)?;
+    check(user_prompt)?;
+    if !system_prompt.contains("{text}") && !user_prompt.contains("{text}") {
+        anyhow::bail!("Neither the system nor the user prompt template contains {{text}}");
+    }
+    Ok(())
+}
+
+/// The template with every variable replaced by its value.
+pub(crate) fn render(template: &str, variables: &Variables) -> String {
+    PLACEHOLDER
+        .replace_all(template, |captures: &regex::Captures| match &captures[1] {
+            "title" => variables.title.to_string(),
+            "url" => variables.url.to_string(),
+            "domain" => variables.domain.clone(),
+            "hn_score" => variables.hn_score.to_string(),
+            "text" => variables.text.to_string(),
+            "audience" => variables.audience.to_string(),
+            "date" => variables.date.clone(),
+            _ => captures[0].to_string(),
+        })
+        .into_owned()
+}
+
+/// Version of a template, the start of the hash of its content.
+pub(crate) fn hash(template: &str) -> String {
+    use sha2::Digest;
+    let hash = format!("{:x}", sha2::Sha256::digest(template.as_bytes()));
+    hash[..16].to_string()
+}
+
+/// Reads a template from a file and checks its variables.
+pub(crate) fn load(path: &str) -> anyhow::Result<String> {
+    let template = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Error reading prompt template {path}: {e}"))?;
+    check(&template).map_err(|e| anyhow::anyhow!("Invalid prompt template {path}: {e}"))?;
+    Ok(template)
+}
+
+/// `YYYY-MM-DD` of a unix timestamp in UTC.
+fn date(unix: i64) -> String {
+    time::OffsetDateTime::from_unix_timestamp(unix)
+        .expect("Timestamp within the years -9999 to 9999")
+        .date()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let story = crate::Story {
+            title: "A new model".to_string(),
+            url: Some("https://www.example.com/post?id=1".to_string()),
+            score: 42,
+            ..Default::default()
+        };
+        let variables = Variables::new(&story, "ML engineers", "The text.");
+
+        assert_eq!(
+            render(DEFAULT_USER_PROMPT, &variables),
+            "Title: A new model\nUrl: https://www.example.com/post?id=1\n\nThe text."
+        );
+        assert_eq!(
+            render(
+                "For {audience}: {domain} ({hn_score} points) {\"json\": true} {unknown}",
+                &variables
+            ),
+            "For ML engineers: example.com (42 points) {\"json\": true} {unknown}"
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        assert!(check(DEFAULT_USER_PROMPT).is_ok());
+        assert!(check("Answer as {\"summary\": ...} on {date}").is_ok());
+        assert!(check("Summarize {titel}").is_err());
+        assert!(check_templates("Summarize", "{title}").is_err());
+        assert!(
+            check_templates(
+                &load("prompts/system.txt").unwrap(),
+                &load("prompts/user.txt").unwrap()
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_date() {
+        assert_eq!(date(0), "1970-01-01");
+        assert_eq!(date(951_782_400), "2000-02-29");
+        assert_eq!(date(1_760_745_600), "2025-10-18");
+    }
+}
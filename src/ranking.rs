@@ -0,0 +1,398 @@
+//! Order of the stories in a digest. Stories are grouped by their best label and ranked within
+//! it by a weighted score over the model's relevance score, Hacker News points and comments, the
+//! domain and the age of the story, decayed like the Hacker News front page. Every part of the
+//! score is kept on the story so a surprising order can be explained.
+
+/// Added to the age in hours, so stories submitted moments ago do not get an unbounded boost.
+const AGE_OFFSET_HOURS: f64 = 2.0;
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub(crate) struct Weights {
+    /// Per point of the 0-100 relevance score.
+    pub(crate) relevance: f64,
+    /// Per unit of the logarithm of the Hacker News points.
+    pub(crate) points: f64,
+    /// Per unit of the logarithm of the number of comments.
+    pub(crate) comments: f64,
+    /// Exponent of the age decay, 0 turns it off. Hacker News uses 1.8 for its front page.
+    pub(crate) gravity: f64,
+    /// Multipliers of domains and their subdomains. Other domains are multiplied by 1.
+    pub(crate) domains: std::collections::BTreeMap<String, f64>,
+}
+
+/// Weights as set by a profile in `PROFILES`. Weights it leaves out are taken from the default.
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct WeightsConfig {
+    relevance: Option<f64>,
+    points: Option<f64>,
+    comments: Option<f64>,
+    gravity: Option<f64>,
+    /// Replaces the default domain weights as a whole.
+    domains: Option<std::collections::BTreeMap<String, f64>>,
+}
+
+impl WeightsConfig {
+    pub(crate) fn or(self, default: &Weights) -> anyhow::Result<Weights> {
+        let domains = match self.domains {
+            Some(domains) => {
+                for (domain, weight) in &domains {
+                    check_domain_weight(domain, *weight)?;
+                }
+                domains
+                    .into_iter()
+                    .map(|(domain, weight)| (domain.to_lowercase(), weight))
+                    .collect()
+            }
+            None => default.domains.clone(),
+        };
+        Ok(Weights {
+            relevance: self.relevance.unwrap_or(default.relevance),
+            points: self.points.unwrap_or(default.points),
+            comments: self.comments.unwrap_or(default.comments),
+            gravity: self.gravity.unwrap_or(default.gravity),
+            domains,
+        })
+    }
+}
+
+/// Parts of a story's ranking score.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub(crate) struct Components {
+    /// Weighted relevance score.
+    pub(crate) relevance: f64,
+    /// Weighted logarithm of the points.
+    pub(crate) points: f64,
+    /// Weighted logarithm of the comments.
+    pub(crate) comments: f64,
+    pub(crate) domain_weight: f64,
+    /// 1.0 for a new story, falling towards 0 with age.
+    pub(crate) decay: f64,
+    /// `(relevance + points + comments) * domain_weight * decay`
+    pub(crate) score: f64,
+}
+
+/// Parses domain weights like `arxiv.org=1.5,medium.com=0.5`.
+pub(crate) fn parse_domain_weights(
+    s: &str,
+) -> anyhow::Result<std::collections::BTreeMap<String, f64>> {
+    let mut domains = std::collections::BTreeMap::new();
+    for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (domain, weight) = entry
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Expected domain=weight, got {entry}"))?;
+        let weight: f64 = weight
+            .trim()
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid weight of {domain}: {e}"))?;
+        check_domain_weight(domain, weight)?;
+        domains.insert(domain.trim().to_lowercase(), weight);
+    }
+    Ok(domains)
+}
+
+fn check_domain_weight(domain: &str, weight: f64) -> anyhow::Result<()> {
+    if !weight.is_finite() || weight < 0.0 {
+        anyhow::bail!("Weight of {domain} has to be a non-negative number");
+    }
+    Ok(())
+}
+
+/// Weight of the most specific configured domain the url belongs to.
+fn domain_weight(weights: &Weights, url: Option<&str>) -> f64 {
+    let Some(mut domain) = url.and_then(crate::triage::domain) else {
+        return 1.0;
+    };
+    loop {
+        if let Some(weight) = weights.domains.get(&domain) {
+            return *weight;
+        }
+        match domain.split_once('.') {
+            Some((_, parent)) if parent.contains('.') => domain = parent.to_string(),
+            _ => return 1.0,
+        }
+    }
+}
+
+/// Ranking score of the story at unix time `now`, with its parts. Stories without a submission
+/// time are treated as new.
+pub(crate) fn components(story: &crate::Story, weights: &Weights, now: i64) -> Components {
+    let relevance = weights.relevance * story.relevance_score.unwrap_or_default().max(0) as f64;
+    let points = weights.points * (story.score.max(0) as f64).ln_1p();
+    let comments = weights.comments * (story.descendants.unwrap_or_default().max(0) as f64).ln_1p();
+    let domain_weight = domain_weight(weights, story.url.as_deref());
+
+    let age_hours = story
+        .time
+        .map_or(0.0, |time| (now - time).max(0) as f64 / 3600.0);
+    let decay = ((age_hours + AGE_OFFSET_HOURS) / AGE_OFFSET_HOURS).powf(-weights.gravity);
+
+    Components {
+        relevance,
+        points,
+        comments,
+        domain_weight,
+        decay,
+        score: (relevance + points + comments) * domain_weight * decay,
+    }
+}
+
+/// Sets the ranking components of every story and sorts them by best label first and score
+/// second. Stories without classifications go last.
+pub(crate) fn rank(stories: &mut [crate::Story], weights: &Weights, now: i64) {
+    for story in stories.iter_mut() {
+        story.ranking = Some(components(story, weights, now));
+    }
+
+    let best_rank = |story: &crate::Story| {
+        story
+            .classifications
+            .as_deref()
+            .map_or(usize::MAX, crate::topics::best_rank)
+    };
+    let score = |story: &crate::Story| story.ranking.map_or(0.0, |r| r.score);
+    stories.sort_by(|a, b| {
+        best_rank(a)
+            .cmp(&best_rank(b))
+            .then_with(|| score(b).total_cmp(&score(a)))
+            .then_with(|| b.score.cmp(&a.score))
+            .then_with(|| a.id.cmp(&b.id))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const NOW: i64 = 1_760_745_600;
+
+    fn weights() -> Weights {
+        Weights {
+            relevance: 1.0,
+            points: 2.0,
+            comments: 1.0,
+            gravity: 0.2,
+            domains: parse_domain_weights("arxiv.org=1.5,medium.com=0.5").unwrap(),
+        }
+    }
+
+    fn classification(label: &str, rank: usize) -> Vec<crate::topics::Classification> {
+        vec![crate::topics::Classification {
+            topic: "AI".to_string(),
+            label: label.to_string(),
+            rank,
+            included: rank == 0,
+        }]
+    }
+
+    fn story(relevance_score: i64, score: i64, descendants: i64, age_secs: i64) -> crate::Story {
+        crate::Story {
+            score,
+            descendants: Some(descendants),
+            time: Some(NOW - age_secs),
+            relevance_score: Some(relevance_score),
+            url: Some("https://example.com/post".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_rank_by_label_and_points() {
+        let mut stories: Vec<_> = [("High", 0, 200), ("Medium", 1, 100), ("Low", 2, 0)]
+            .into_iter()
+            .chain([("High", 0, 400), ("Medium", 1, 300), ("High", 0, 300)])
+            .enumerate()
+            .map(|(id, (label, rank, score))| crate::Story {
+                id: id as i64,
+                score,
+                classifications: Some(classification(label, rank)),
+                ..Default::default()
+            })
+            .collect();
+
+        rank(&mut stories, &weights(), NOW);
+
+        // Grouped by label, then the most voted first
+        let ids: Vec<_> = stories.iter().map(|s| s.id).collect();
+        assert_eq!(ids, [3, 5, 0, 4, 1, 2]);
+    }
+
+    #[test]
+    fn test_rank_by_relevance_score() {
+        let mut stories = vec![
+            crate::Story {
+                id: 0,
+                classifications: Some(classification("High", 0)),
+                ..story(70, 500, 0, 0)
+            },
+            crate::Story {
+                id: 1,
+                classifications: Some(classification("High", 0)),
+                ..story(95, 10, 0, 0)
+            },
+            crate::Story {
+                id: 2,
+                classifications: Some(classification("Medium", 1)),
+                ..story(99, 900, 0, 0)
+            },
+            crate::Story {
+                id: 3,
+                ..story(100, 1000, 0, 0)
+            },
+        ];
+
+        rank(&mut stories, &weights(), NOW);
+
+        // The relevance score outweighs votes within a label, unclassified stories go last
+        let ids: Vec<_> = stories.iter().map(|s| s.id).collect();
+        assert_eq!(ids, [1, 0, 2, 3]);
+        assert!(stories.iter().all(|s| s.ranking.is_some()));
+    }
+
+    #[test]
+    fn test_domain_weight() {
+        let weights = weights();
+        assert_eq!(
+            domain_weight(&weights, Some("https://arxiv.org/abs/1")),
+            1.5
+        );
+        assert_eq!(
+            domain_weight(&weights, Some("https://export.arxiv.org/abs/1")),
+            1.5
+        );
+        assert_eq!(
+            domain_weight(&weights, Some("https://blog.medium.com/p")),
+            0.5
+        );
+        assert_eq!(domain_weight(&weights, Some("https://notarxiv.org")), 1.0);
+        assert_eq!(domain_weight(&weights, None), 1.0);
+        assert!(parse_domain_weights("arxiv.org").is_err());
+        assert!(parse_domain_weights("arxiv.org=-1").is_err());
+    }
+
+    #[test]
+    fn test_weights_config() {
+        let config: WeightsConfig =
+            serde_json::from_str(r#"{"points": 0.5, "domains": {"Example.com": 2.0}}"#).unwrap();
+        let profile_weights = config.or(&weights()).unwrap();
+        assert_eq!(profile_weights.points, 0.5);
+        assert_eq!(profile_weights.relevance, 1.0);
+        assert_eq!(profile_weights.gravity, 0.2);
+        assert_eq!(
+            profile_weights.domains,
+            [("example.com".to_string(), 2.0)].into()
+        );
+
+        let config: WeightsConfig = serde_json::from_str(r#"{"gravity": 1.8}"#).unwrap();
+        assert_eq!(config.or(&weights()).unwrap().domains, weights().domains);
+
+        let config: WeightsConfig =
+            serde_json::from_str(r#"{"domains": {"example.com": -1}}"#).unwrap();
+        assert!(config.or(&weights()).is_err());
+    }
+
+    prop_compose! {
+        fn arbitrary_story()(
+            relevance_score in -10i64..=110,
+            score in -10i64..100_000,
+            descendants in 0i64..10_000,
+            age_secs in -3600i64..30 * 24 * 3600,
+        ) -> crate::Story {
+            story(relevance_score, score, descendants, age_secs)
+        }
+    }
+
+    prop_compose! {
+        fn arbitrary_weights()(
+            relevance in 0.0..10.0,
+            points in 0.0..10.0,
+            comments in 0.0..10.0,
+            gravity in 0.0..3.0,
+        ) -> Weights {
+            Weights { relevance, points, comments, gravity, ..weights() }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_score_is_finite_and_non_negative(
+            story in arbitrary_story(),
+            weights in arbitrary_weights(),
+        ) {
+            let c = components(&story, &weights, NOW);
+            prop_assert!(c.score.is_finite() && c.score >= 0.0);
+            prop_assert!(c.decay > 0.0 && c.decay <= 1.0);
+            prop_assert_eq!(
+                c.score,
+                (c.relevance + c.points + c.comments) * c.domain_weight * c.decay
+            );
+        }
+
+        #[test]
+        fn test_score_grows_with_relevance_points_and_comments(
+            story in arbitrary_story(),
+            weights in arbitrary_weights(),
+            more in 0i64..1000,
+        ) {
+            let score = components(&story, &weights, NOW).score;
+            let better = [
+                crate::Story {
+                    relevance_score: story.relevance_score.map(|r| r + more),
+                    ..story.clone()
+                },
+                crate::Story { score: story.score + more, ..story.clone() },
+                crate::Story { descendants: story.descendants.map(|d| d + more), ..story.clone() },
+            ];
+            for better in better {
+                prop_assert!(components(&better, &weights, NOW).score >= score);
+            }
+        }
+
+        #[test]
+        fn test_score_decays_with_age(
+            story in arbitrary_story(),
+            weights in arbitrary_weights(),
+            older_by in 0i64..30 * 24 * 3600,
+        ) {
+            let older = crate::Story { time: story.time.map(|t| t - older_by), ..story.clone() };
+            prop_assert!(
+                components(&older, &weights, NOW).score <= components(&story, &weights, NOW).score
+            );
+        }
+
+        #[test]
+        fn test_rank_orders_by_label_then_score(
+            stories in proptest::collection::vec((arbitrary_story(), proptest::option::of(0usize..4)), 0..20),
+            weights in arbitrary_weights(),
+        ) {
+            let mut stories: Vec<_> = stories
+                .into_iter()
+                .enumerate()
+                .map(|(id, (story, rank))| crate::Story {
+                    id: id as i64,
+                    classifications: rank.map(|rank| classification("Label", rank)),
+                    ..story
+                })
+                .collect();
+            let num_stories = stories.len();
+
+            rank(&mut stories, &weights, NOW);
+
+            let mut ids: Vec<_> = stories.iter().map(|s| s.id).collect();
+            ids.sort();
+            prop_assert_eq!(ids, (0..num_stories as i64).collect::<Vec<_>>());
+            for pair in stories.windows(2) {
+                let best_rank = |s: &crate::Story| {
+                    s.classifications.as_deref().map_or(usize::MAX, crate::topics::best_rank)
+                };
+                prop_assert!(best_rank(&pair[0]) <= best_rank(&pair[1]));
+                if best_rank(&pair[0]) == best_rank(&pair[1]) {
+                    prop_assert!(
+                        pair[0].ranking.unwrap().score >= pair[1].ranking.unwrap().score
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,181 @@
+//! Bookkeeping for a single summarizer run. Counts and per-story errors are collected while
+//! the pipeline executes and persisted to the `runs` table once it finishes.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Stage {
+    Fetch,
+    Triage,
+    Scrape,
+    Summarize,
+}
+
+impl std::fmt::Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stage::Fetch => write!(f, "fetch"),
+            Stage::Triage => write!(f, "triage"),
+            Stage::Scrape => write!(f, "scrape"),
+            Stage::Summarize => write!(f, "summarize"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    Success,
+    Failure,
+    Timeout,
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Success => write!(f, "success"),
+            Outcome::Failure => write!(f, "failure"),
+            Outcome::Timeout => write!(f, "timeout"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct StoryError {
+    pub(crate) story_id: i64,
+    pub(crate) stage: Stage,
+    pub(crate) error: String,
+}
+
+/// A story dropped because a stage ran out of time. Not a failure of the story itself.
+#[derive(Debug, Clone)]
+pub(crate) struct SkippedStory {
+    pub(crate) story_id: i64,
+    pub(crate) stage: Stage,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Run {
+    pub(crate) id: i64,
+
+    pub(crate) stories_fetched: usize,
+    /// Stories remaining after job adverts, url-less, filtered out and already processed stories
+    /// are removed.
+    pub(crate) stories_filtered: usize,
+    /// Stories left after triage, before scraping.
+    pub(crate) stories_triaged: usize,
+    pub(crate) stories_scraped: usize,
+    pub(crate) stories_scored: usize,
+    pub(crate) stories_sent: usize,
+    /// Stories dropped because a stage ran out of time.
+    pub(crate) stories_skipped: usize,
+
+    pub(crate) usage: crate::openai::Usage,
+    pub(crate) errors: Vec<StoryError>,
+    /// Stories dropped at a deadline, kept apart from `errors` so they do not count as failures.
+    pub(crate) skipped: Vec<SkippedStory>,
+    /// Filter rules that matched a story.
+    pub(crate) filter_matches: Vec<crate::filters::Match>,
+    /// Profiles whose digest had no story meeting the threshold.
+    pub(crate) fallbacks: Vec<crate::fallback::Fallback>,
+}
+
+impl Run {
+    /// Inserts a new run row and returns the in-memory record used to collect statistics. The
+    /// run is recorded with the models of the profiles it builds digests for.
+    pub(crate) fn start(
+        db: &rusqlite::Connection,
+        profiles: &[&crate::profile::Profile],
+    ) -> anyhow::Result<Self> {
+        let started_at = unix_now();
+        let mut models: Vec<&str> = Vec::new();
+        for profile in profiles {
+            if !models.contains(&profile.model.as_str()) {
+                models.push(&profile.model);
+            }
+        }
+        let id = crate::db::insert_run(
+            db,
+            started_at,
+            &crate::config::config().hash(),
+            &models.join(", "),
+        )?;
+
+        tracing::info!(run_id = id, "Started run");
+
+        Ok(Self {
+            id,
+            ..Default::default()
+        })
+    }
+
+    pub(crate) fn record_error(&mut self, story_id: i64, stage: Stage, error: &anyhow::Error) {
+        tracing::error!(error =? error, story_id = story_id, stage =% stage, "Story failed");
+        self.errors.push(StoryError {
+            story_id,
+            stage,
+            error: format!("{error:#}"),
+        });
+    }
+
+    /// Collects task results until `deadline`. Tasks still running are aborted and recorded as
+    /// skipped so the pipeline can continue with what has finished.
+    pub(crate) async fn join_until<T: 'static>(
+        &mut self,
+        join_set: &mut tokio::task::JoinSet<(i64, anyhow::Result<T>)>,
+        mut pending: std::collections::HashSet<i64>,
+        deadline: tokio::time::Instant,
+        stage: Stage,
+    ) -> Vec<T> {
+        let mut results = Vec::with_capacity(join_set.len());
+
+        loop {
+            match tokio::time::timeout_at(deadline, join_set.join_next()).await {
+                Ok(Some(result)) => {
+                    let (id, result) = result.expect("JoinSet to work");
+                    pending.remove(&id);
+                    match result {
+                        Ok(result) => results.push(result),
+                        Err(e) => self.record_error(id, stage, &e),
+                    }
+                }
+                Ok(None) => break,
+                Err(_) => {
+                    tracing::warn!(
+                        stage =% stage,
+                        num_skipped = pending.len(),
+                        "Stage deadline exceeded, continuing with finished stories"
+                    );
+                    join_set.abort_all();
+                    self.stories_skipped += pending.len();
+                    for story_id in pending {
+                        self.skipped.push(SkippedStory { story_id, stage });
+                    }
+                    break;
+                }
+            }
+        }
+
+        results
+    }
+
+    pub(crate) fn finish(
+        &self,
+        db: &rusqlite::Connection,
+        outcome: Outcome,
+        error: Option<&str>,
+    ) -> anyhow::Result<()> {
+        crate::db::finish_run(db, self, unix_now(), outcome, error)?;
+        tracing::info!(
+            run_id = self.id,
+            outcome =% outcome,
+            num_errors = self.errors.len(),
+            "Finished run"
+        );
+        Ok(())
+    }
+}
+
+pub(crate) fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
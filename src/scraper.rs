@@ -25,12 +25,14 @@ async fn scrape_and_trim_text(story: &crate::Story, export_text: bool) -> anyhow
 pub(crate) async fn enrich_stories(
     stories: Vec<crate::Story>,
     export_text: bool,
+    run: &mut crate::run::Run,
 ) -> anyhow::Result<Vec<crate::Story>> {
-    let mut scraped_stories = Vec::with_capacity(stories.len());
+    let deadline = crate::config::deadline(crate::config::config().scrape_deadline_secs);
 
-    let mut queries_set: tokio::task::JoinSet<anyhow::Result<crate::Story>> =
+    let mut queries_set: tokio::task::JoinSet<(i64, anyhow::Result<crate::Story>)> =
         tokio::task::JoinSet::new();
 
+    let pending = stories.iter().map(|s| s.id).collect();
     for mut story in stories {
         queries_set.spawn(async move {
             let title = story.title.clone();
@@ -40,11 +42,12 @@ pub(crate) async fn enrich_stories(
             let trimmed_text = backoff::future::retry_notify(
                 crate::backoff::backoff_default(),
                 || async {
-                    Ok(tokio::select! {
+                    tokio::select! {
                         res = scrape_and_trim_text(&story, export_text) => res,
                         _ = tokio::time::sleep(std::time::Duration::from_secs(30)) => {
                             Err(anyhow::anyhow!("Timeout when scraping story"))},
-                    }?)
+                    }
+                    .map_err(crate::cassette::retryable)
                 },
                 |e, duration: std::time::Duration| {
                     tracing::warn!(
@@ -57,26 +60,30 @@ pub(crate) async fn enrich_stories(
                     )
                 },
             )
-            .await?;
-
-            story.text = Some(trimmed_text);
-
-            Ok(story)
+            .await;
+
+            match trimmed_text {
+                Ok(trimmed_text) => {
+                    story.text = Some(trimmed_text);
+                    (id, Ok(story))
+                }
+                Err(e) => (id, Err(e)),
+            }
         });
     }
 
-    while let Some(res) = queries_set.join_next().await {
-        match res.expect("JoinSet to work") {
-            Ok(text) => scraped_stories.push(text),
-            Err(e) => tracing::error!(error =? e, "Error scraping story"),
-        }
-    }
-
-    Ok(scraped_stories)
+    Ok(run
+        .join_until(
+            &mut queries_set,
+            pending,
+            deadline,
+            crate::run::Stage::Scrape,
+        )
+        .await)
 }
 
 async fn scrape_text(url: &str) -> anyhow::Result<String> {
-    let response = crate::CLIENT.get(url).send().await?.text().await?;
+    let response = crate::cassette::send(crate::CLIENT.get(url)).await?.body;
     tracing::info!(num_characters = response.len(), "Scraped {}", url);
     Ok(response)
 }
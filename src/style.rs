@@ -0,0 +1,166 @@
+//! Output style of a profile's summaries: the language they are written in, who they are written
+//! for and how long they are. The style is added to the system prompt and to the descriptions in
+//! the response schema. Articles in another language than the profile's are translated, and the
+//! detected language of the article is recorded.
+
+/// Only the start of the text is used to detect its language, more does not change the result.
+const LANGUAGE_DETECTION_CHARS: usize = 2000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ReadingLevel {
+    /// Technical terms, numbers and names are kept as they are.
+    Technical,
+    /// Jargon is avoided or explained, for readers outside engineering.
+    General,
+}
+
+impl std::str::FromStr for ReadingLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "technical" => Ok(ReadingLevel::Technical),
+            "general" => Ok(ReadingLevel::General),
+            _ => anyhow::bail!("Unknown reading level {s}, expected technical or general"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Length {
+    Short,
+    Standard,
+    Deep,
+}
+
+impl std::str::FromStr for Length {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "short" => Ok(Length::Short),
+            "standard" => Ok(Length::Standard),
+            "deep" => Ok(Length::Deep),
+            _ => anyhow::bail!("Unknown summary length {s}, expected short, standard or deep"),
+        }
+    }
+}
+
+impl Length {
+    /// Range of key points asked for.
+    fn key_points(self) -> (usize, usize) {
+        match self {
+            Length::Short => (2, 3),
+            Length::Standard => (3, 5),
+            Length::Deep => (5, 7),
+        }
+    }
+}
+
+/// Parses an ISO 639-3 language code like `eng` or `swe`.
+pub(crate) fn parse_language(code: &str) -> anyhow::Result<whatlang::Lang> {
+    whatlang::Lang::from_code(code.to_lowercase())
+        .ok_or_else(|| anyhow::anyhow!("Unknown language code {code}, expected ISO 639-3 like eng"))
+}
+
+/// Language of the text if it can be told reliably.
+pub(crate) fn detect_language(text: &str) -> Option<whatlang::Lang> {
+    let end = text
+        .char_indices()
+        .nth(LANGUAGE_DETECTION_CHARS)
+        .map_or(text.len(), |(index, _)| index);
+    whatlang::detect(&text[..end])
+        .filter(whatlang::Info::is_reliable)
+        .map(|info| info.lang())
+}
+
+/// Instructions added to the system prompt. `source_language` is the detected language of the
+/// article, if known.
+pub(crate) fn instructions(
+    profile: &crate::profile::Profile,
+    source_language: Option<whatlang::Lang>,
+) -> String {
+    let language = parse_language(&profile.language).expect("Validated language");
+    let mut instructions = format!("Write the summary in {}.", language.eng_name());
+    if let Some(source) = source_language
+        && source != language
+    {
+        instructions.push_str(&format!(
+            " The text is in {}, translate everything you write into {}.",
+            source.eng_name(),
+            language.eng_name()
+        ));
+    }
+
+    instructions.push_str(match profile.reading_level {
+        ReadingLevel::Technical => {
+            " The readers are technical, keep technical terms, numbers and names of models and \
+            products as they are."
+        }
+        ReadingLevel::General => {
+            " The readers are not technical, avoid jargon, explain technical terms in plain words \
+            and focus on what the news means rather than how it works."
+        }
+    });
+
+    let (min, max) = profile.length.key_points();
+    instructions.push_str(&match profile.length {
+        Length::Short => format!(" Keep it short, with {min} to {max} key points."),
+        Length::Standard => format!(" Give {min} to {max} key points."),
+        Length::Deep => format!(
+            " Go into depth, with {min} to {max} key points covering the approach, the results \
+            and the limitations."
+        ),
+    });
+
+    instructions
+}
+
+/// Adds the profile's language and length to the descriptions of the summary in the schema.
+pub(crate) fn describe_summary(schema: &mut serde_json::Value, profile: &crate::profile::Profile) {
+    let language = parse_language(&profile.language)
+        .expect("Validated language")
+        .eng_name();
+    let (min, max) = profile.length.key_points();
+    let reader = match profile.reading_level {
+        ReadingLevel::Technical => "a technical reader",
+        ReadingLevel::General => "a non-technical reader",
+    };
+
+    let properties = &mut schema["properties"]["summary"]["properties"];
+    properties["tldr"]["description"] =
+        format!("A single sentence summing up the text, in {language}").into();
+    properties["key_points"]["description"] = format!(
+        "The {min} to {max} most important points of the text, one short sentence each, in \
+        {language}"
+    )
+    .into();
+    properties["why_it_matters"]["description"] =
+        format!("A single sentence on why this matters to {reader}, in {language}").into();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_language() {
+        assert_eq!(
+            detect_language(
+                "The model was trained on a large corpus of public source code and it is \
+                available for anyone to download and use in their own products."
+            ),
+            Some(whatlang::Lang::Eng)
+        );
+        assert_eq!(
+            detect_language(
+                "Modellen tränades på en stor mängd öppen källkod och den finns tillgänglig för \
+                alla att ladda ner och använda i sina egna produkter."
+            ),
+            Some(whatlang::Lang::Swe)
+        );
+        assert_eq!(detect_language(""), None);
+    }
+}
@@ -0,0 +1,323 @@
+//! Token budgeting for texts sent to the model. Tokens are counted locally with the tokenizer of
+//! the configured model family, so prompt sizes are known before anything is sent. Scraped pages
+//! can be far larger than the model's context, so they are truncated to a budget and split into
+//! chunks that each fit, preferring to cut between paragraphs, then lines, then sentences.
+
+/// Boundaries to split at, from most to least preferred.
+const SEPARATORS: [&str; 4] = ["\n\n", "\n", ". ", " "];
+
+/// A character is at most four bytes and the tokenizers never produce more than one token per
+/// byte, so pieces of `max_tokens / MAX_TOKENS_PER_CHAR` characters always fit.
+const MAX_TOKENS_PER_CHAR: usize = 4;
+
+/// Tokenizer used by `model`. Unknown models fall back to the one of the current OpenAI models.
+fn tokenizer(model: &str) -> &'static tiktoken_rs::CoreBPE {
+    use tiktoken_rs::tokenizer::Tokenizer;
+
+    match tiktoken_rs::tokenizer::get_tokenizer(model) {
+        Some(Tokenizer::Cl100kBase) => tiktoken_rs::cl100k_base_singleton(),
+        Some(Tokenizer::P50kBase) => tiktoken_rs::p50k_base_singleton(),
+        Some(Tokenizer::P50kEdit) => tiktoken_rs::p50k_edit_singleton(),
+        Some(Tokenizer::R50kBase | Tokenizer::Gpt2) => tiktoken_rs::r50k_base_singleton(),
+        Some(Tokenizer::O200kBase) | None => tiktoken_rs::o200k_base_singleton(),
+    }
+}
+
+/// Number of tokens `text` is encoded into for `model`.
+pub(crate) fn count(model: &str, text: &str) -> usize {
+    tokenizer(model).encode_ordinary(text).len()
+}
+
+/// Byte offsets in `text` at which its tokens start. Tokens can end inside a multi-byte
+/// character, so the offsets are not always character boundaries.
+fn token_starts(model: &str, text: &str) -> Vec<usize> {
+    let tokenizer = tokenizer(model);
+    let mut start = 0;
+    tokenizer
+        ._decode_native_and_split(tokenizer.encode_ordinary(text))
+        .map(|bytes| {
+            let token_start = start;
+            start += bytes.len();
+            token_start
+        })
+        .collect()
+}
+
+/// Largest of the ascending `ends` for which `text[start..end]` fits `max_tokens`, if any.
+///
+/// The tokens of the whole text give an estimate for every end without encoding again. Token
+/// counts are not additive across a cut, so the chosen end is counted exactly and the next
+/// smaller end is tried while it does not fit, which is rarely more than once.
+fn fit(
+    model: &str,
+    text: &str,
+    starts: &[usize],
+    start: usize,
+    ends: &[usize],
+    max_tokens: usize,
+) -> Option<usize> {
+    let first = starts.partition_point(|s| *s < start);
+    let straddling = starts.get(first) != Some(&start);
+    let estimate = |end: usize| starts.partition_point(|s| *s < end) - first + straddling as usize;
+
+    let mut i = ends.partition_point(|end| estimate(*end) <= max_tokens);
+    while i > 0 && count(model, &text[start..ends[i - 1]]) > max_tokens {
+        i -= 1;
+    }
+    i.checked_sub(1).map(|i| ends[i])
+}
+
+/// Splits `text` into pieces of at most `max_tokens`, cutting at the first separator that makes
+/// the pieces small enough. Pieces without any separator are cut at character boundaries.
+fn units<'a>(model: &str, text: &'a str, max_tokens: usize, separators: &[&str]) -> Vec<&'a str> {
+    if count(model, text) <= max_tokens {
+        return vec![text];
+    }
+
+    match separators.split_first() {
+        Some((separator, rest)) => text
+            .split_inclusive(separator)
+            .flat_map(|piece| units(model, piece, max_tokens, rest))
+            .collect(),
+        None => {
+            let max_chars = (max_tokens / MAX_TOKENS_PER_CHAR).max(1);
+            let mut pieces = Vec::new();
+            let mut start = 0;
+            for (i, (index, _)) in text.char_indices().enumerate() {
+                if i > 0 && i % max_chars == 0 {
+                    pieces.push(&text[start..index]);
+                    start = index;
+                }
+            }
+            pieces.push(&text[start..]);
+            pieces
+        }
+    }
+}
+
+/// Splits `text` into consecutive chunks of at most `max_tokens` each.
+pub(crate) fn chunk(model: &str, text: &str, max_tokens: usize) -> Vec<String> {
+    let max_tokens = max_tokens.max(MAX_TOKENS_PER_CHAR);
+    let ends: Vec<usize> = units(model, text, max_tokens, &SEPARATORS)
+        .iter()
+        .scan(0, |end, unit| {
+            *end += unit.len();
+            Some(*end)
+        })
+        .collect();
+    let starts = token_starts(model, text);
+
+    // Every unit fits on its own, so each chunk takes at least one.
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < text.len() {
+        let next = ends.partition_point(|end| *end <= start);
+        let end = fit(model, text, &starts, start, &ends[next..], max_tokens).unwrap_or(ends[next]);
+        chunks.push(text[start..end].to_string());
+        start = end;
+    }
+    if chunks.last().is_some_and(|c| c.trim().is_empty()) {
+        chunks.pop();
+    }
+
+    chunks
+}
+
+/// Sentences of `text` including their trailing punctuation and whitespace.
+fn sentences(text: &str) -> Vec<&str> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((index, c)) = chars.next() {
+        let end_of_sentence = match c {
+            '\n' => true,
+            '.' | '!' | '?' => chars.peek().is_none_or(|(_, next)| next.is_whitespace()),
+            _ => false,
+        };
+        if end_of_sentence {
+            // Keep the whitespace after the sentence with it.
+            let mut end = index + c.len_utf8();
+            while let Some((next_index, next)) = chars.peek() {
+                if !next.is_whitespace() {
+                    break;
+                }
+                end = next_index + next.len_utf8();
+                chars.next();
+            }
+            sentences.push(&text[start..end]);
+            start = end;
+        }
+    }
+    if start < text.len() {
+        sentences.push(&text[start..]);
+    }
+
+    sentences
+}
+
+/// Longest prefix of `text` made of whole sentences that fits `max_tokens`. If not even the
+/// first sentence fits, it is cut at a character boundary instead.
+pub(crate) fn truncate<'a>(model: &str, text: &'a str, max_tokens: usize) -> &'a str {
+    let starts = token_starts(model, text);
+    if starts.len() <= max_tokens {
+        return text;
+    }
+
+    let ends: Vec<usize> = sentences(text)
+        .iter()
+        .scan(0, |end, sentence| {
+            *end += sentence.len();
+            Some(*end)
+        })
+        .collect();
+    let mut end = fit(model, text, &starts, 0, &ends, max_tokens).unwrap_or(0);
+
+    if end == 0 {
+        let max_chars = max_tokens / MAX_TOKENS_PER_CHAR;
+        end = text
+            .char_indices()
+            .nth(max_chars)
+            .map(|(index, _)| index)
+            .unwrap_or(text.len());
+    }
+
+    &text[..end]
+}
+
+/// Runs tokenizer work off the async runtime, encoding a long scraped page takes a while.
+pub(crate) async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> T + Send + 'static,
+) -> anyhow::Result<T> {
+    Ok(tokio::task::spawn_blocking(f).await?)
+}
+
+/// Dollars per million tokens of a model.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub(crate) struct Prices {
+    pub(crate) input: f64,
+    pub(crate) output: f64,
+}
+
+/// Parses prices like `gpt-4o=2.5/10,gpt-4o-mini=0.15/0.6`, input before output.
+pub(crate) fn parse_prices(s: &str) -> anyhow::Result<std::collections::BTreeMap<String, Prices>> {
+    let mut prices = std::collections::BTreeMap::new();
+    for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (model, price) = entry
+            .split_once('=')
+            .and_then(|(model, price)| Some((model, price.split_once('/')?)))
+            .ok_or_else(|| anyhow::anyhow!("Expected model=input/output, got {entry}"))?;
+        let parse = |price: &str| {
+            price
+                .trim()
+                .parse::<f64>()
+                .map_err(|e| anyhow::anyhow!("Invalid price of {model}: {e}"))
+        };
+        prices.insert(
+            model.trim().to_string(),
+            Prices {
+                input: parse(price.0)?,
+                output: parse(price.1)?,
+            },
+        );
+    }
+    Ok(prices)
+}
+
+/// Cost in dollars of `usage` at the prices of `model`. Models without their own prices use
+/// `INPUT_PRICE_PER_MILLION_TOKENS` and `OUTPUT_PRICE_PER_MILLION_TOKENS`.
+pub(crate) fn cost(model: &str, usage: &crate::openai::Usage) -> f64 {
+    let config = crate::config::config();
+    let prices = config.model_prices.get(model).copied().unwrap_or(Prices {
+        input: config.input_price_per_million_tokens,
+        output: config.output_price_per_million_tokens,
+    });
+    (usage.prompt_tokens as f64 * prices.input + usage.completion_tokens as f64 * prices.output)
+        / 1_000_000.0
+}
+
+/// Cost in dollars of usage spread over several models, each at its own prices.
+pub(crate) fn total_cost(
+    usage_by_model: &std::collections::BTreeMap<String, crate::openai::Usage>,
+) -> f64 {
+    usage_by_model
+        .iter()
+        .fold(0.0, |total, (model, usage)| total + cost(model, usage))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODEL: &str = "gpt-4o-mini";
+
+    #[test]
+    fn test_count() {
+        assert_eq!(count(MODEL, ""), 0);
+        assert_eq!(count(MODEL, "hello world"), 2);
+        assert_eq!(count("gpt-4-0613", "hello world"), 2);
+    }
+
+    #[test]
+    fn test_parse_prices() {
+        let prices = parse_prices("gpt-4o=2.5/10, gpt-4o-mini = 0.15/0.6").unwrap();
+        assert_eq!(
+            prices["gpt-4o"],
+            Prices {
+                input: 2.5,
+                output: 10.0
+            }
+        );
+        assert_eq!(prices["gpt-4o-mini"].output, 0.6);
+        assert!(parse_prices("").unwrap().is_empty());
+        assert!(parse_prices("gpt-4o=2.5").is_err());
+        assert!(parse_prices("gpt-4o=cheap/10").is_err());
+    }
+
+    #[test]
+    fn test_chunk() {
+        assert_eq!(chunk(MODEL, "Short text.", 100), vec!["Short text."]);
+
+        let text = format!(
+            "{}\n\n{}\n{}. {}",
+            "alpha ".repeat(30),
+            "beta ".repeat(30),
+            "gamma ".repeat(50),
+            "delta".repeat(90)
+        );
+        let chunks = chunk(MODEL, &text, 20);
+        assert!(chunks.iter().all(|c| count(MODEL, c) <= 20), "{chunks:?}");
+        assert_eq!(chunks.concat(), text);
+        assert!(chunks.len() > 1);
+
+        let text = "é".repeat(100);
+        let chunks = chunk(MODEL, &text, 8);
+        assert!(chunks.iter().all(|c| count(MODEL, c) <= 8), "{chunks:?}");
+        assert_eq!(chunks.concat(), text);
+    }
+
+    #[test]
+    fn test_truncate() {
+        let text = "First sentence here. Second one! Third?\nFourth line.";
+        assert_eq!(truncate(MODEL, text, 100), text);
+        assert_eq!(
+            truncate(MODEL, text, 8),
+            "First sentence here. Second one! "
+        );
+        assert_eq!(truncate(MODEL, text, 6), "First sentence here. ");
+        assert_eq!(
+            truncate(MODEL, "Version 1.5 is out.", 100),
+            "Version 1.5 is out."
+        );
+        assert_eq!(truncate(MODEL, &"word".repeat(100), 8), "wo");
+
+        // The longest fitting prefix of whole sentences is kept from a long text.
+        let text = (0..2000)
+            .map(|i| format!("Sentence number {i} is here. "))
+            .collect::<String>();
+        let truncated = truncate(MODEL, &text, 1000);
+        let next = sentences(&text[truncated.len()..])[0];
+        assert!(count(MODEL, truncated) <= 1000);
+        assert!(count(MODEL, &text[..truncated.len() + next.len()]) > 1000);
+    }
+}
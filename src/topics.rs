@@ -0,0 +1,228 @@
+//! Topics the stories are classified against. Each topic has its own ordered set of labels and
+//! an inclusion threshold. The response schema sent to the model is generated from them.
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub(crate) struct Topic {
+    /// Used as the property name in the response schema and shown in the digest.
+    pub(crate) name: String,
+    /// Instructions to the model on what the labels mean for this topic.
+    pub(crate) description: String,
+    /// Ordered from most to least relevant.
+    pub(crate) labels: Vec<String>,
+    /// Least relevant label that still makes a story eligible for the digest.
+    pub(crate) threshold: String,
+}
+
+/// The label a story got for one topic.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub(crate) struct Classification {
+    pub(crate) topic: String,
+    pub(crate) label: String,
+    /// Position of the label in the topic's label list. Lower is more relevant.
+    pub(crate) rank: usize,
+    /// Whether the label meets the topic's threshold.
+    pub(crate) included: bool,
+}
+
+impl Topic {
+    fn rank(&self, label: &str) -> Option<usize> {
+        self.labels.iter().position(|l| l == label)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.name.is_empty()
+            || !self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            anyhow::bail!(
+                "Topic name {:?} must be non-empty and only contain a-z, A-Z, 0-9, _ or -",
+                self.name
+            );
+        }
+        if self.labels.is_empty() {
+            anyhow::bail!("Topic {} has no labels", self.name);
+        }
+        if self.rank(&self.threshold).is_none() {
+            anyhow::bail!(
+                "Threshold {} of topic {} is not one of its labels",
+                self.threshold,
+                self.name
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The original AI impact scoring.
+pub(crate) fn default_topics() -> Vec<Topic> {
+    vec![Topic {
+        name: "AI".to_string(),
+        description: "Impact of the text on the AI community".to_string(),
+        labels: ["High", "Medium", "Low", "Zero"]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        threshold: "High".to_string(),
+    }]
+}
+
+pub(crate) fn parse_topics(json: &str) -> anyhow::Result<Vec<Topic>> {
+    let topics: Vec<Topic> = serde_json::from_str(json)?;
+    validate_topics(&topics)?;
+    Ok(topics)
+}
+
+pub(crate) fn validate_topics(topics: &[Topic]) -> anyhow::Result<()> {
+    if topics.is_empty() {
+        anyhow::bail!("At least one topic has to be configured");
+    }
+    for (i, topic) in topics.iter().enumerate() {
+        topic.validate()?;
+        if topics[..i].iter().any(|t| t.name == topic.name) {
+            anyhow::bail!("Topic {} is configured more than once", topic.name);
+        }
+    }
+    Ok(())
+}
+
+/// JSON schema for the `topics` object of the model response. Every topic is a required string
+/// property restricted to its labels.
+pub(crate) fn schema(topics: &[Topic]) -> serde_json::Value {
+    let properties: serde_json::Map<String, serde_json::Value> = topics
+        .iter()
+        .map(|topic| {
+            (
+                topic.name.clone(),
+                serde_json::json!({
+                    "type": "string",
+                    "description": format!(
+                        "{}. One of {}, ordered from most to least relevant.",
+                        topic.description,
+                        topic.labels.join(", ")
+                    ),
+                    "enum": topic.labels,
+                }),
+            )
+        })
+        .collect();
+
+    serde_json::json!({
+        "type": "object",
+        "description": "Relevance label for each topic",
+        "properties": properties,
+        "required": topics.iter().map(|t| &t.name).collect::<Vec<_>>(),
+        "additionalProperties": false,
+    })
+}
+
+/// Checks the labels returned by the model against the configured topics.
+pub(crate) fn classify(
+    topics: &[Topic],
+    labels: &std::collections::BTreeMap<String, String>,
+) -> anyhow::Result<Vec<Classification>> {
+    topics
+        .iter()
+        .map(|topic| {
+            let label = labels
+                .get(&topic.name)
+                .ok_or(anyhow::anyhow!("Missing label for topic {}", topic.name))?;
+            let rank = topic.rank(label).ok_or(anyhow::anyhow!(
+                "Unknown label {label} for topic {}",
+                topic.name
+            ))?;
+            Ok(Classification {
+                topic: topic.name.clone(),
+                label: label.clone(),
+                rank,
+                included: rank <= topic.rank(&topic.threshold).expect("Validated threshold"),
+            })
+        })
+        .collect()
+}
+
+/// A story is eligible for the digest if any of its topics meets its threshold.
+pub(crate) fn is_included(classifications: &[Classification]) -> bool {
+    classifications.iter().any(|c| c.included)
+}
+
+/// A story missed the digest by one label if any of its topics got the label right below the
+/// topic's threshold.
+pub(crate) fn is_near_miss(topics: &[Topic], classifications: &[Classification]) -> bool {
+    classifications.iter().any(|c| {
+        topics
+            .iter()
+            .find(|t| t.name == c.topic)
+            .and_then(|t| t.rank(&t.threshold))
+            .is_some_and(|threshold| c.rank == threshold + 1)
+    })
+}
+
+/// Most relevant rank over all topics, used for ordering.
+pub(crate) fn best_rank(classifications: &[Classification]) -> usize {
+    classifications
+        .iter()
+        .map(|c| c.rank)
+        .min()
+        .unwrap_or(usize::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let topics = parse_topics(
+            r#"[
+                {"name": "security", "description": "Security relevance", "labels": ["Critical", "Notable", "None"], "threshold": "Notable"},
+                {"name": "rust", "description": "Rust relevance", "labels": ["Yes", "No"], "threshold": "Yes"}
+            ]"#,
+        )
+        .unwrap();
+
+        let labels = [("security", "Notable"), ("rust", "No")]
+            .into_iter()
+            .map(|(t, l)| (t.to_string(), l.to_string()))
+            .collect();
+        let classifications = classify(&topics, &labels).unwrap();
+
+        assert_eq!(classifications[0].rank, 1);
+        assert!(classifications[0].included);
+        assert!(!classifications[1].included);
+        assert!(is_included(&classifications));
+        assert_eq!(best_rank(&classifications), 1);
+
+        let labels = [("security", "None"), ("rust", "No")]
+            .into_iter()
+            .map(|(t, l)| (t.to_string(), l.to_string()))
+            .collect();
+        let classifications = classify(&topics, &labels).unwrap();
+        assert!(!is_included(&classifications));
+        assert!(is_near_miss(&topics, &classifications));
+
+        let labels = [("security", "Bogus"), ("rust", "No")]
+            .into_iter()
+            .map(|(t, l)| (t.to_string(), l.to_string()))
+            .collect();
+        assert!(classify(&topics, &labels).is_err());
+    }
+
+    #[test]
+    fn test_invalid_topics() {
+        assert!(parse_topics("[]").is_err());
+        assert!(
+            parse_topics(
+                r#"[{"name": "ai", "description": "", "labels": ["High"], "threshold": "Low"}]"#
+            )
+            .is_err()
+        );
+        assert!(
+            parse_topics(
+                r#"[{"name": "a i", "description": "", "labels": ["High"], "threshold": "High"}]"#
+            )
+            .is_err()
+        );
+    }
+}
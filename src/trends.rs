@@ -0,0 +1,223 @@
+//! Entity tagging and trend queries. The entities the model extracts from each summary are
+//! normalized into the `tags` table and linked to their stories, which lets us count mentions
+//! per week and spot entities that only recently started showing up.
+
+const SECONDS_PER_WEEK: i64 = 7 * 24 * 60 * 60;
+
+/// Suffixes dropped when normalizing so "Anthropic" and "Anthropic, Inc." end up as one tag.
+const CORPORATE_SUFFIXES: [&str; 6] = [" inc.", " inc", " ltd.", " ltd", " llc", " corp."];
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub(crate) struct WeeklyMentions {
+    /// ISO 8601 week like `2025-W42`.
+    pub(crate) week: String,
+    pub(crate) kind: String,
+    pub(crate) name: String,
+    pub(crate) mentions: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub(crate) struct EmergingEntity {
+    pub(crate) kind: String,
+    pub(crate) name: String,
+    pub(crate) first_seen: i64,
+    pub(crate) mentions: i64,
+}
+
+/// What to look for in the trends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Query {
+    /// Number of weeks of mentions to count.
+    pub(crate) weeks: i64,
+    /// Number of entities kept per week.
+    pub(crate) top: usize,
+    /// Entities first mentioned within this many weeks count as emerging.
+    pub(crate) emerging_weeks: i64,
+    /// Minimum number of stories for an entity to count as emerging.
+    pub(crate) min_mentions: i64,
+}
+
+impl Default for Query {
+    fn default() -> Self {
+        Self {
+            weeks: 8,
+            top: 10,
+            emerging_weeks: 2,
+            min_mentions: 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub(crate) struct Trends {
+    pub(crate) weekly: Vec<WeeklyMentions>,
+    pub(crate) emerging: Vec<EmergingEntity>,
+}
+
+/// Key used to merge different spellings of the same entity.
+pub(crate) fn normalize(name: &str) -> String {
+    let mut normalized = name
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+
+    for suffix in CORPORATE_SUFFIXES {
+        if let Some(stripped) = normalized.strip_suffix(suffix) {
+            normalized = stripped.trim_end_matches(',').to_string();
+        }
+    }
+
+    normalized
+}
+
+/// Links every entity in the stories' summaries to the story.
+pub(crate) fn tag_stories(
+    db: &rusqlite::Connection,
+    stories: &[crate::Story],
+) -> anyhow::Result<()> {
+    let tagged_at = crate::run::unix_now();
+
+    for story in stories {
+        let Some(summary) = &story.summary else {
+            continue;
+        };
+        let entities = &summary.entities;
+        let tags = [
+            ("company", &entities.companies),
+            ("model", &entities.models),
+            ("person", &entities.people),
+            ("product", &entities.products),
+        ];
+
+        for (kind, names) in tags {
+            for name in names {
+                let normalized = normalize(name);
+                if normalized.is_empty() {
+                    continue;
+                }
+                let tag_id = crate::db::upsert_tag(db, kind, name.trim(), &normalized)?;
+                crate::db::insert_story_tag(db, story.id, tag_id, tagged_at)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Mention counts for the `top` most mentioned entities of each of the last `weeks` weeks before
+/// `now`, and the entities first seen within the last `emerging_weeks` with at least
+/// `min_mentions`. The `trends` command prints this, other callers can use it directly.
+pub(crate) fn query(db: &rusqlite::Connection, query: &Query, now: i64) -> anyhow::Result<Trends> {
+    let Query {
+        weeks,
+        top,
+        emerging_weeks,
+        min_mentions,
+    } = *query;
+
+    let mut weekly = crate::db::get_weekly_mentions(db, now - weeks * SECONDS_PER_WEEK)?;
+    // Rows come ordered by week and then mentions, so keeping the first `top` of each week
+    // keeps the most mentioned ones.
+    let mut kept_in_week = 0;
+    let mut current_week = String::new();
+    weekly.retain(|mentions| {
+        if mentions.week != current_week {
+            current_week.clone_from(&mentions.week);
+            kept_in_week = 0;
+        }
+        kept_in_week += 1;
+        kept_in_week <= top
+    });
+
+    let emerging = crate::db::get_emerging_entities(
+        db,
+        now - emerging_weeks * SECONDS_PER_WEEK,
+        min_mentions,
+    )?;
+
+    Ok(Trends { weekly, emerging })
+}
+
+pub(crate) fn print_trends(trends: &Trends, json: bool) -> anyhow::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(trends)?);
+        return Ok(());
+    }
+
+    println!(
+        "{:<8} {:<8} {:<30} {:>8}",
+        "Week", "Kind", "Entity", "Mentions"
+    );
+    for mentions in &trends.weekly {
+        println!(
+            "{:<8} {:<8} {:<30} {:>8}",
+            mentions.week, mentions.kind, mentions.name, mentions.mentions
+        );
+    }
+
+    println!("\nEmerging entities");
+    for entity in &trends.emerging {
+        println!(
+            "{:<8} {:<30} {:>8}",
+            entity.kind, entity.name, entity.mentions
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("  OpenAI "), "openai");
+        assert_eq!(normalize("Anthropic, Inc."), "anthropic");
+        assert_eq!(normalize("Hugging   Face"), "hugging face");
+        assert_eq!(normalize("Llama 3.1 405B"), "llama 3.1 405b");
+    }
+
+    #[test]
+    fn test_query() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        crate::db::init_db(&db, false).unwrap();
+
+        // Monday 2024-12-30 belongs to the first ISO week of 2025.
+        let new_year_week = 1_735_516_800;
+        let openai = crate::db::upsert_tag(&db, "company", "OpenAI", "openai").unwrap();
+        let mistral = crate::db::upsert_tag(&db, "company", "Mistral", "mistral").unwrap();
+        crate::db::insert_story_tag(&db, 1, openai, new_year_week - 30 * 24 * 60 * 60).unwrap();
+        crate::db::insert_story_tag(&db, 2, openai, new_year_week).unwrap();
+        crate::db::insert_story_tag(&db, 2, mistral, new_year_week).unwrap();
+        crate::db::insert_story_tag(&db, 3, mistral, new_year_week + 60).unwrap();
+
+        let trends = query(
+            &db,
+            &Query {
+                top: 1,
+                ..Default::default()
+            },
+            new_year_week + 24 * 60 * 60,
+        )
+        .unwrap();
+
+        assert_eq!(
+            trends
+                .weekly
+                .iter()
+                .map(|m| (m.week.as_str(), m.name.as_str(), m.mentions))
+                .collect::<Vec<_>>(),
+            [("2025-W01", "Mistral", 2), ("2024-W48", "OpenAI", 1)]
+        );
+        assert_eq!(
+            trends
+                .emerging
+                .iter()
+                .map(|e| e.name.as_str())
+                .collect::<Vec<_>>(),
+            ["Mistral"]
+        );
+    }
+}
@@ -0,0 +1,323 @@
+//! First pass over the fetched stories before anything is scraped. Only titles and domains are
+//! scored, either in batches by a cheap model or locally by keyword matching, and stories below
+//! the threshold are dropped so the main model only sees plausible candidates.
+
+/// Score a single keyword match is worth in keyword mode.
+const KEYWORD_MATCH_SCORE: i64 = 50;
+
+/// Assumed completion tokens per story of a batch, for estimates. A score like
+/// `{"id":41234567,"score":80},` is about a dozen tokens.
+const ESTIMATED_SCORE_TOKENS: i64 = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Mode {
+    /// Every story is scraped and summarized.
+    Off,
+    /// Titles and domains are matched against `TRIAGE_KEYWORDS`.
+    Keywords,
+    /// Titles and domains are scored in batches by `TRIAGE_MODEL`.
+    Model,
+}
+
+impl std::str::FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "off" => Ok(Mode::Off),
+            "keywords" => Ok(Mode::Keywords),
+            "model" => Ok(Mode::Model),
+            _ => anyhow::bail!("Unknown triage mode {s}, expected off, keywords or model"),
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+struct Candidate<'a> {
+    id: i64,
+    title: &'a str,
+    domain: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct TriageResponse {
+    #[schemars(required)]
+    #[schemars(description = "One score for every story")]
+    scores: Vec<TriageScore>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct TriageScore {
+    #[schemars(required)]
+    #[schemars(description = "Id of the story")]
+    id: i64,
+
+    #[schemars(required)]
+    #[schemars(
+        description = "Likelihood from 0 (certainly irrelevant) to 100 (certainly relevant) that the story is relevant to at least one topic"
+    )]
+    score: i64,
+}
+
+fn schema() -> crate::openai::Schema {
+    crate::openai::Schema {
+        name: "triage_scores".to_string(),
+        schema: crate::openai::schema_for::<TriageResponse>(),
+        strict: true,
+    }
+}
+
+/// Host of the url without a leading `www.`.
+pub(crate) fn domain(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).ok()?;
+    let host = url.host_str()?;
+    Some(host.strip_prefix("www.").unwrap_or(host).to_lowercase())
+}
+
+/// System prompt rating the stories against the topics of every profile.
+fn system_prompt(profiles: &[&crate::profile::Profile]) -> String {
+    let mut topics: Vec<&crate::topics::Topic> = Vec::new();
+    for topic in profiles.iter().flat_map(|p| &p.topics) {
+        if !topics.iter().any(|t| t.name == topic.name) {
+            topics.push(topic);
+        }
+    }
+
+    let mut prompt = "You triage Hacker News stories before they are read. For every story in \
+        the json list, judging only by its title and domain, rate how likely it is to be \
+        relevant to at least one of these topics:\n"
+        .to_string();
+    for topic in topics {
+        prompt.push_str(&format!("- {}: {}\n", topic.name, topic.description));
+    }
+    prompt.push_str(
+        "Stories rated low are discarded unread, so only rate a story low if it is clearly off \
+        topic.",
+    );
+    prompt
+}
+
+/// Local stand-in for the model. Every keyword found in the title or domain adds
+/// `KEYWORD_MATCH_SCORE`, capped at 100.
+pub(crate) fn keyword_score(title: &str, domain: Option<&str>, keywords: &[String]) -> i64 {
+    let words: Vec<String> = title
+        .split(|c: char| !c.is_alphanumeric() && c != '+' && c != '#')
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    let title = words.join(" ");
+
+    let matches = keywords
+        .iter()
+        .map(|k| k.to_lowercase())
+        .filter(|k| {
+            // Multi word keywords match as a phrase, single words have to match a whole word.
+            let in_title = if k.contains(' ') {
+                format!(" {title} ").contains(&format!(" {k} "))
+            } else {
+                words.contains(k)
+            };
+            // Domains match on whole labels, so "ai" does not match mail.google.com.
+            let in_domain = domain.is_some_and(|d| format!(".{d}.").contains(&format!(".{k}.")));
+            in_title || in_domain
+        })
+        .count() as i64;
+
+    (matches * KEYWORD_MATCH_SCORE).min(100)
+}
+
+/// The batch as the json list the model scores.
+fn content(batch: &[crate::Story]) -> anyhow::Result<String> {
+    let candidates: Vec<Candidate> = batch
+        .iter()
+        .map(|s| Candidate {
+            id: s.id,
+            title: &s.title,
+            domain: s.url.as_deref().and_then(domain),
+        })
+        .collect();
+    Ok(serde_json::to_string(&candidates)?)
+}
+
+async fn score_batch(
+    batch: &[crate::Story],
+    system_prompt: &str,
+) -> anyhow::Result<(std::collections::HashMap<i64, i64>, crate::openai::Usage)> {
+    let (response, usage): (TriageResponse, _) = crate::openai::complete(
+        &crate::config::config().triage_model,
+        system_prompt,
+        &content(batch)?,
+        schema(),
+    )
+    .await?;
+
+    Ok((
+        response
+            .scores
+            .into_iter()
+            .map(|s| (s.id, s.score.clamp(0, 100)))
+            .collect(),
+        usage,
+    ))
+}
+
+/// Scores the stories and drops the ones below `TRIAGE_THRESHOLD`. Stories that could not be
+/// scored, because the model failed, skipped them or ran out of time, are kept, and so are
+/// stories a filter rule always includes.
+pub(crate) async fn triage(
+    mut stories: Vec<crate::Story>,
+    profiles: &[&crate::profile::Profile],
+    run: &mut crate::run::Run,
+) -> Vec<crate::Story> {
+    let config = crate::config::config();
+
+    match config.triage_mode {
+        Mode::Off => return stories,
+        Mode::Keywords => {
+            for story in &mut stories {
+                let domain = story.url.as_deref().and_then(domain);
+                story.triage_score = Some(keyword_score(
+                    &story.title,
+                    domain.as_deref(),
+                    &config.triage_keywords,
+                ));
+            }
+        }
+        Mode::Model => {
+            let system_prompt = system_prompt(profiles);
+            let deadline = crate::config::deadline(config.triage_deadline_secs);
+
+            let mut usage = crate::openai::Usage::default();
+            for batch in stories.chunks_mut(config.triage_batch_size.max(1)) {
+                let result =
+                    tokio::time::timeout_at(deadline, score_batch(batch, &system_prompt)).await;
+                match result {
+                    Ok(Ok((scores, batch_usage))) => {
+                        usage += &batch_usage;
+                        for story in batch.iter_mut() {
+                            story.triage_score = scores.get(&story.id).copied();
+                        }
+                    }
+                    Ok(Err(e)) => {
+                        for story in batch.iter() {
+                            run.record_error(story.id, crate::run::Stage::Triage, &e);
+                        }
+                    }
+                    Err(_) => {
+                        tracing::warn!("Triage deadline exceeded, keeping the remaining stories");
+                        break;
+                    }
+                }
+            }
+
+            tracing::info!(usage =? usage, "Triaged stories with model");
+            run.usage += &usage;
+        }
+    }
+
+    let (kept, discarded): (Vec<_>, Vec<_>) = stories.into_iter().partition(|s| {
+        s.filter_rule.is_some()
+            || s.triage_score
+                .is_none_or(|score| score >= config.triage_threshold)
+    });
+
+    for story in &discarded {
+        tracing::info!(
+            title = story.title,
+            triage_score = story.triage_score,
+            "Discarded story in triage"
+        );
+    }
+    tracing::info!(
+        num_kept = kept.len(),
+        num_discarded = discarded.len(),
+        "Finished triage"
+    );
+
+    kept
+}
+
+/// Expected usage of model triage without calling the model. Prompt tokens are counted exactly,
+/// completion tokens are assumed. Other modes do not call a model.
+pub(crate) fn estimate_usage(
+    stories: &[crate::Story],
+    profiles: &[&crate::profile::Profile],
+) -> anyhow::Result<crate::openai::Usage> {
+    let config = crate::config::config();
+    let mut usage = crate::openai::Usage::default();
+    if config.triage_mode != Mode::Model {
+        return Ok(usage);
+    }
+
+    let model = &config.triage_model;
+    let system_prompt_tokens = crate::tokens::count(model, &system_prompt(profiles)) as i64;
+    for batch in stories.chunks(config.triage_batch_size.max(1)) {
+        usage.prompt_tokens +=
+            system_prompt_tokens + crate::tokens::count(model, &content(batch)?) as i64;
+        usage.completion_tokens += ESTIMATED_SCORE_TOKENS * batch.len() as i64;
+    }
+    usage.total_tokens = usage.prompt_tokens + usage.completion_tokens;
+
+    Ok(usage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keyword_score() {
+        let keywords: Vec<String> = ["llm", "machine learning", "huggingface"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        assert_eq!(
+            keyword_score("Show HN: A tiny LLM in C", None, &keywords),
+            50
+        );
+        assert_eq!(
+            keyword_score("Machine learning for LLMs", Some("arxiv.org"), &keywords),
+            50
+        );
+        assert_eq!(
+            keyword_score("LLM quantization", Some("huggingface.co"), &keywords),
+            100
+        );
+        assert_eq!(
+            keyword_score("Rust 1.85 released", Some("blog.rust-lang.org"), &keywords),
+            0
+        );
+
+        let keywords = ["ai".to_string(), "arxiv.org".to_string()];
+        for domain in ["mail.google.com", "daily.co", "airbnb.com", "openai.com"] {
+            assert_eq!(
+                keyword_score("A post", Some(domain), &keywords),
+                0,
+                "{domain}"
+            );
+        }
+        assert_eq!(keyword_score("A post", Some("ai.meta.com"), &keywords), 50);
+        assert_eq!(
+            keyword_score("A post", Some("export.arxiv.org"), &keywords),
+            50
+        );
+    }
+
+    #[test]
+    fn test_domain() {
+        assert_eq!(
+            domain("https://www.Example.com/a?b=c"),
+            Some("example.com".to_string())
+        );
+        assert_eq!(domain("not a url"), None);
+    }
+
+    #[test]
+    fn test_schema_is_strict() {
+        crate::openai::tests::assert_strict(&schema().schema);
+    }
+}
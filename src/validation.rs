@@ -0,0 +1,140 @@
+//! Checks on the summaries the model returns. A response can follow the schema and still be
+//! useless: empty or one-word points, refusals like "I cannot access the article" or a summary in
+//! the wrong language. Failed checks are described in plain sentences so they can be sent back to
+//! the model to repair the summary.
+
+/// Texts shorter than this are not checked for their language, detection is unreliable on them.
+const MIN_LANGUAGE_DETECTION_CHARS: usize = 40;
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub(crate) struct Rules {
+    pub(crate) min_key_points: usize,
+    pub(crate) max_key_points: usize,
+    /// Bounds on the number of words of the tl;dr, every key point and "why it matters".
+    pub(crate) min_words: usize,
+    pub(crate) max_words: usize,
+    /// Matched case insensitively anywhere in the summary.
+    pub(crate) forbidden_phrases: Vec<String>,
+}
+
+/// Problems with the summary, empty if it passes every rule and is written in `language`.
+pub(crate) fn validate(
+    summary: &crate::openai::Summary,
+    rules: &Rules,
+    language: whatlang::Lang,
+) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    let num_key_points = summary.key_points.len();
+    if num_key_points < rules.min_key_points || num_key_points > rules.max_key_points {
+        errors.push(format!(
+            "There are {num_key_points} key points, there have to be between {} and {}.",
+            rules.min_key_points, rules.max_key_points
+        ));
+    }
+
+    let sentences = std::iter::once(("The tl;dr", &summary.tldr))
+        .chain(summary.key_points.iter().map(|p| ("A key point", p)))
+        .chain(std::iter::once(("Why it matters", &summary.why_it_matters)));
+    for (name, sentence) in sentences {
+        let num_words = sentence.split_whitespace().count();
+        if num_words < rules.min_words || num_words > rules.max_words {
+            errors.push(format!(
+                "{name} has {num_words} words, it has to have between {} and {}: {sentence:?}",
+                rules.min_words, rules.max_words
+            ));
+        }
+    }
+
+    let text = std::iter::once(summary.tldr.as_str())
+        .chain(summary.key_points.iter().map(String::as_str))
+        .chain(std::iter::once(summary.why_it_matters.as_str()))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let lowercase = text.to_lowercase();
+    for phrase in &rules.forbidden_phrases {
+        if lowercase.contains(&phrase.to_lowercase()) {
+            errors.push(format!(
+                "The summary contains the forbidden phrase {phrase:?}."
+            ));
+        }
+    }
+
+    if text.chars().count() >= MIN_LANGUAGE_DETECTION_CHARS
+        && let Some(detected) = crate::style::detect_language(&text)
+        && detected != language
+    {
+        errors.push(format!(
+            "The summary is written in {}, it has to be written in {}.",
+            detected.eng_name(),
+            language.eng_name()
+        ));
+    }
+
+    errors
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn summary(tldr: &str, key_points: &[&str]) -> crate::openai::Summary {
+        crate::openai::Summary {
+            tldr: tldr.to_string(),
+            key_points: key_points.iter().map(|p| p.to_string()).collect(),
+            why_it_matters: "It changes how teams deploy open models in production.".to_string(),
+            content_type: crate::openai::ContentType::News,
+            entities: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let rules = Rules {
+            min_key_points: 2,
+            max_key_points: 4,
+            min_words: 3,
+            max_words: 30,
+            forbidden_phrases: vec!["cannot access".to_string()],
+        };
+        let eng = whatlang::Lang::Eng;
+
+        let valid = summary(
+            "A lab released an open weights model that rivals closed ones.",
+            &[
+                "The model has 70 billion parameters.",
+                "It is released under a permissive license.",
+            ],
+        );
+        assert!(validate(&valid, &rules, eng).is_empty());
+
+        let errors = validate(&summary("Model released.", &["Fast"]), &rules, eng);
+        assert_eq!(errors.len(), 3, "{errors:?}");
+
+        let refusal = summary(
+            "I cannot access the article at this url.",
+            &["No content was available.", "Please provide the text."],
+        );
+        assert_eq!(
+            validate(&refusal, &rules, eng),
+            vec!["The summary contains the forbidden phrase \"cannot access\"."]
+        );
+
+        let german = summary(
+            "Ein Labor hat ein offenes Modell veröffentlicht, das mit geschlossenen mithalten kann.",
+            &[
+                "Das Modell hat siebzig Milliarden Parameter.",
+                "Es steht unter einer freien Lizenz und darf kommerziell genutzt werden.",
+            ],
+        );
+        let german = crate::openai::Summary {
+            why_it_matters: "Es verändert, wie Teams offene Modelle betreiben.".to_string(),
+            ..german
+        };
+        let errors = validate(&german, &rules, eng);
+        assert_eq!(
+            errors,
+            vec!["The summary is written in German, it has to be written in English."]
+        );
+    }
+}
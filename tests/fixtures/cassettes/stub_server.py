@@ -0,0 +1,77 @@
+"""Local stand-ins for Hacker News, two article pages, OpenAI and the chat webhook, used to record
+the `daily` cassette. Recording against them instead of a real daily run keeps the cassette free of
+live stories, paid model output and secrets, and makes re-recording reproducible without network:
+
+    python3 tests/fixtures/cassettes/stub_server.py &
+    cp tests/fixtures/cassettes/daily.env /tmp/record/.env && cd /tmp/record
+    CASSETTE_MODE=record CASSETTE_DIR=<repo>/tests/fixtures/cassettes/daily ai-summarizer --no-cache
+
+Re-record whenever a change to the prompts, schemas or rendering is intended.
+"""
+import json
+from http.server import BaseHTTPRequestHandler, HTTPServer
+
+SUMMARY = {
+    "summary": {
+        "tldr": "A new open model sets the state of the art on coding benchmarks.",
+        "key_points": ["The model is released under an open licence.", "It beats larger models on coding benchmarks."],
+        "why_it_matters": "Open models keep closing the gap to proprietary ones.",
+        "content_type": "Release",
+        "entities": {"companies": ["Example Labs"], "models": ["Example-1"], "people": [], "products": []},
+    },
+    "topics": {"AI": "High"},
+    "relevance_score": 85,
+    "rationale": "A major open model release.",
+    "confidence": 0.9,
+}
+CHUNK = {"key_points": ["This part describes the model release."]}
+
+BASE = "http://127.0.0.1:8765"
+ITEMS = {
+    "1001": {"by": "alice", "descendants": 42, "id": 1001, "score": 310, "time": 1760000000, "title": "Example-1: an open model for code", "type": "story", "url": BASE + "/articles/example-1"},
+    "1002": {"by": "bob", "id": 1002, "score": 12, "time": 1760000100, "title": "Example Labs is hiring", "type": "job", "url": ""},
+    "1003": {"by": "carol", "descendants": 7, "id": 1003, "score": 95, "time": 1760000200, "title": "Notes on training Example-1", "type": "story", "url": BASE + "/articles/training-notes"},
+}
+SHORT = "<html><head><title>Example-1</title></head><body><h1>Example-1</h1><p>Example Labs released <a href='/m'>Example-1</a>, an open model for code.</p><p>It beats larger models on coding benchmarks.</p></body></html>"
+LONG = "<html><body><h1>Training notes</h1>" + "".join(
+    f"<h2>Step {i}</h2><p>In step {i} we tuned the learning rate, checked the loss curves and wrote down what went wrong. The data mix changed slightly and evaluation was repeated on the held out set.</p>"
+    for i in range(1, 25)
+) + "</body></html>"
+PAGES = {"/articles/example-1": SHORT, "/articles/training-notes": LONG}
+
+class H(BaseHTTPRequestHandler):
+    def do_GET(self):
+        ctype = "application/json"
+        if self.path == "/v0/topstories.json":
+            data = json.dumps([1001, 1002, 1003])
+        elif self.path.startswith("/v0/item/"):
+            data = json.dumps(ITEMS.get(self.path[len("/v0/item/"):-len(".json")]))
+        elif self.path in PAGES:
+            data, ctype = PAGES[self.path], "text/html"
+        else:
+            self.send_response(404); self.end_headers(); return
+        data = data.encode()
+        self.send_response(200)
+        self.send_header("Content-Type", ctype)
+        self.send_header("Content-Length", str(len(data)))
+        self.end_headers()
+        self.wfile.write(data)
+
+    def do_POST(self):
+        body = json.loads(self.rfile.read(int(self.headers["Content-Length"])))
+        if self.path == "/v1/chat/completions":
+            name = body["response_format"]["json_schema"]["name"]
+            content = CHUNK if name == "chunk_summary" else SUMMARY
+            out = {"id": "chatcmpl-replay", "object": "chat.completion", "created": 1760000000, "model": body["model"],
+                   "choices": [{"index": 0, "finish_reason": "stop", "message": {"role": "assistant", "content": json.dumps(content)}}],
+                   "usage": {"prompt_tokens": 1000, "completion_tokens": 200, "total_tokens": 1200}}
+        else:
+            out = {}
+        data = json.dumps(out).encode()
+        self.send_response(200)
+        self.send_header("Content-Type", "application/json")
+        self.send_header("Content-Length", str(len(data)))
+        self.end_headers()
+        self.wfile.write(data)
+
+HTTPServer(("127.0.0.1", 8765), H).serve_forever()